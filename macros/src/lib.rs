@@ -68,14 +68,13 @@ fn expand(
     let const_ident = Ident::new(&const_name, function_ident.span());
 
     let path = arg.value();
-    let query_index = path.find('?');
+    let query_index = query_start(&path);
 
     let url = if let Some(index) = query_index { &path[..index] } else { &path };
     let query_part = if let Some(index) = query_index { &path[index + 1..] } else { "" };
 
     let params = url.split('/')
-        .filter(|s| s.starts_with('{') && s.ends_with('}'))
-        .map(|s| &s[1..s.len() - 1])
+        .filter_map(param_name)
         .collect::<Vec<&str>>();
 
     let query_params: HashMap<String, String> = query_part.split('&')
@@ -112,13 +111,16 @@ fn expand(
                 if let syn::Pat::Ident(ident) = &*pat_type.pat {
                     let name = ident.ident.to_string();
                     if params.contains(&name.as_str()) {
-                        let extractor = quote! { altaria::extractor::param::Param::<#type_path> };
+                        let extractor = match extract_option_type_param(type_path) {
+                            Some(type_path) => quote! { altaria::extractor::param::OptionalParam::<#type_path> },
+                            None => quote! { altaria::extractor::param::Param::<#type_path> }
+                        };
                         let access = quote! { #variable_ident.0 };
                         accesses.push(access);
                         idents.push(variable_ident.clone());
                         extractors.push(extractor.clone());
                         extractions.push(quote! {
-                            let #variable_ident = #extractor::from_request(#index, &mut request).await?;
+                            let #variable_ident = #extractor::from_request_by_name(#name, &request)?;
                         });
                         continue;
                     } else if query_params.contains_key(&name) {
@@ -138,6 +140,16 @@ fn expand(
                             let #variable_ident = #extractor::from_request_by_name(#actual_name, &request)?;
                         });
                         continue;
+                    } else if is_named_param(type_path) {
+                        // A parameter from outside the endpoint's own path, such as the prefix it's nested under
+                        let extractor = type_path.to_token_stream();
+                        accesses.push(quote! { #variable_ident });
+                        idents.push(variable_ident.clone());
+                        extractors.push(extractor.clone());
+                        extractions.push(quote! {
+                            let #variable_ident = <#extractor>::from_request_by_name(#name, &request)?;
+                        });
+                        continue;
                     }
                 }
                 let extractor_name = type_path.to_token_stream().to_string().replace("<", "::<").replace(" ", "");
@@ -185,7 +197,7 @@ fn expand(
             }
 
             async fn handle_request(&self, mut request: altaria::request::HttpRequest) -> altaria::response::HttpResponse {
-                let extract_values = async {
+                let extract_values = async move {
                     use altaria::extractor::FromRequest;
                     use altaria::extractor::query::NamedExtractor;
                     #(#extractions)*
@@ -206,6 +218,33 @@ fn expand(
     })
 }
 
+/// Where the query part of a route pattern starts, skipping the `?` of optional segments.
+fn query_start(path: &str) -> Option<usize> {
+    let mut depth = 0;
+    path.char_indices().find_map(|(index, char)| {
+        match char {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            '?' if depth == 0 => return Some(index),
+            _ => {}
+        }
+        None
+    })
+}
+
+/// The name of a path parameter segment, such as `id` for `{id}`, `{id?}`, `{id:u64}` or `{*id}`.
+fn param_name(segment: &str) -> Option<&str> {
+    let name = segment.strip_prefix('{')?.strip_suffix('}')?;
+    let name = name.split_once(':').map_or(name, |(name, _)| name);
+    let name = name.strip_suffix('?').unwrap_or(name);
+    Some(name.strip_prefix('*').unwrap_or(name))
+}
+
+/// Whether the type is a `Param<T>` or `OptionalParam<T>`, which are named after the argument.
+fn is_named_param(type_path: &syn::TypePath) -> bool {
+    type_path.path.segments.last().is_some_and(|segment| segment.ident == "Param" || segment.ident == "OptionalParam")
+}
+
 fn extract_option_type_param(type_path: &syn::TypePath) -> Option<syn::Type> {
     if let Some(segment) = type_path.path.segments.last() {
         if segment.ident == "Option" {
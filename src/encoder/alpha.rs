@@ -1,4 +1,6 @@
+use crate::request::{HttpHeader, HeaderMap};
 use crate::response::HttpResponse;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
 
 pub struct AlphaHttpEncoder {
 }
@@ -15,7 +17,7 @@ impl AlphaHttpEncoder {
         encoded.extend_from_slice(b"\r\n");
     }
 
-    pub fn encode(&self, response: HttpResponse) -> crate::Result<Vec<u8>> {
+    fn encode_head(&self, response: &HttpResponse) -> Vec<u8> {
         let mut encoded = Vec::new();
         encoded.extend_from_slice(b"HTTP/1.1 ");
         encoded.extend_from_slice(response.status_code.code().to_string().as_bytes());
@@ -26,8 +28,64 @@ impl AlphaHttpEncoder {
         }
 
         encoded.extend_from_slice(b"\r\n");
-        encoded.extend_from_slice(&response.body);
+        encoded
+    }
 
-        Ok(encoded)
+    /// Writes only the head of the response, as the answer to a `HEAD` request. It keeps the
+    /// framing headers the body would have been sent with, and the body is dropped unread.
+    pub async fn write_head<W>(&self, response: HttpResponse, writer: &mut W) -> crate::Result<()>
+    where W: AsyncWrite + Unpin {
+        writer.write_all(&self.encode_head(&response)).await?;
+        Ok(())
     }
-}
\ No newline at end of file
+
+    /// Writes the response to the client. The body is sent with chunked transfer-encoding
+    /// if the headers ask for it, or as-is otherwise, and dropped if the status doesn't allow one.
+    pub async fn write<W>(&self, mut response: HttpResponse, writer: &mut W) -> crate::Result<()>
+    where W: AsyncWrite + Unpin {
+        if !response.status_code.allows_body() {
+            return self.write_head(response, writer).await;
+        }
+        let mut encoded = self.encode_head(&response);
+        if !is_chunked(&response.headers) {
+            // Keeping in-memory bodies in the same write as the head spares a syscall
+            if let Some(bytes) = response.body.as_bytes() {
+                encoded.extend_from_slice(bytes);
+                writer.write_all(&encoded).await?;
+                return Ok(());
+            }
+            writer.write_all(&encoded).await?;
+            while let Some(chunk) = response.body.next_chunk().await {
+                writer.write_all(&chunk?).await?;
+            }
+            return Ok(());
+        }
+
+        writer.write_all(&encoded).await?;
+        while let Some(chunk) = response.body.next_chunk().await {
+            let chunk = chunk?;
+            let mut encoded = Vec::with_capacity(chunk.len() + 12);
+            encoded.extend_from_slice(format!("{:X}\r\n", chunk.len()).as_bytes());
+            encoded.extend_from_slice(&chunk);
+            encoded.extend_from_slice(b"\r\n");
+            writer.write_all(&encoded).await?;
+        }
+
+        let mut encoded = b"0\r\n".to_vec();
+        if let Some(trailers) = response.body.trailers().await {
+            for (key, value) in trailers.iter() {
+                self.insert_header(&key.name(), value, &mut encoded);
+            }
+        }
+        encoded.extend_from_slice(b"\r\n");
+        writer.write_all(&encoded).await?;
+        Ok(())
+    }
+}
+
+fn is_chunked(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(&HttpHeader::TransferEncoding)
+        .last()
+        .is_some_and(|value| value.rsplit(',').next().is_some_and(|coding| coding.trim().eq_ignore_ascii_case("chunked")))
+}
@@ -0,0 +1,74 @@
+use crate::parser::beta::{ErrorCode, FrameKind, Setting, FLAG_ACK, FLAG_END_HEADERS, FLAG_END_STREAM};
+
+pub struct BetaHttpEncoder {
+}
+
+impl BetaHttpEncoder {
+    pub fn new() -> Self {
+        BetaHttpEncoder {}
+    }
+
+    fn insert_frame(&self, kind: FrameKind, flags: u8, stream_id: u32, payload: &[u8], encoded: &mut Vec<u8>) {
+        let length = (payload.len() as u32).to_be_bytes();
+        encoded.extend_from_slice(&length[1..]);
+        encoded.push(kind as u8);
+        encoded.push(flags);
+        encoded.extend_from_slice(&(stream_id & 0x7fff_ffff).to_be_bytes());
+        encoded.extend_from_slice(payload);
+    }
+
+    pub(crate) fn encode_settings(&self, settings: &[Setting], encoded: &mut Vec<u8>) {
+        let mut payload = Vec::with_capacity(settings.len() * 6);
+        for setting in settings {
+            let (identifier, value) = setting.to_pair();
+            payload.extend_from_slice(&identifier.to_be_bytes());
+            payload.extend_from_slice(&value.to_be_bytes());
+        }
+        self.insert_frame(FrameKind::Settings, 0, 0, &payload, encoded);
+    }
+
+    pub(crate) fn encode_settings_ack(&self, encoded: &mut Vec<u8>) {
+        self.insert_frame(FrameKind::Settings, FLAG_ACK, 0, &[], encoded);
+    }
+
+    pub(crate) fn encode_ping_ack(&self, payload: [u8; 8], encoded: &mut Vec<u8>) {
+        self.insert_frame(FrameKind::Ping, FLAG_ACK, 0, &payload, encoded);
+    }
+
+    pub(crate) fn encode_goaway(&self, last_stream_id: u32, error_code: ErrorCode, encoded: &mut Vec<u8>) {
+        let mut payload = Vec::with_capacity(8);
+        payload.extend_from_slice(&(last_stream_id & 0x7fff_ffff).to_be_bytes());
+        payload.extend_from_slice(&(error_code as u32).to_be_bytes());
+        self.insert_frame(FrameKind::GoAway, 0, 0, &payload, encoded);
+    }
+
+    pub(crate) fn encode_rst_stream(&self, stream_id: u32, error_code: ErrorCode, encoded: &mut Vec<u8>) {
+        self.insert_frame(FrameKind::RstStream, 0, stream_id, &(error_code as u32).to_be_bytes(), encoded);
+    }
+
+    pub(crate) fn encode_window_update(&self, stream_id: u32, increment: u32, encoded: &mut Vec<u8>) {
+        self.insert_frame(FrameKind::WindowUpdate, 0, stream_id, &(increment & 0x7fff_ffff).to_be_bytes(), encoded);
+    }
+
+    /// Encodes a header block, splitting it into CONTINUATION frames when it doesn't fit
+    /// in a single frame.
+    pub(crate) fn encode_headers(&self, stream_id: u32, block: &[u8], end_stream: bool, max_frame_size: usize, encoded: &mut Vec<u8>) {
+        let mut fragments = block.chunks(max_frame_size).peekable();
+        let first = fragments.next().unwrap_or_default();
+        let mut flags = if end_stream { FLAG_END_STREAM } else { 0 };
+        if fragments.peek().is_none() {
+            flags |= FLAG_END_HEADERS;
+        }
+        self.insert_frame(FrameKind::Headers, flags, stream_id, first, encoded);
+
+        while let Some(fragment) = fragments.next() {
+            let flags = if fragments.peek().is_none() { FLAG_END_HEADERS } else { 0 };
+            self.insert_frame(FrameKind::Continuation, flags, stream_id, fragment, encoded);
+        }
+    }
+
+    pub(crate) fn encode_data(&self, stream_id: u32, data: &[u8], end_stream: bool, encoded: &mut Vec<u8>) {
+        let flags = if end_stream { FLAG_END_STREAM } else { 0 };
+        self.insert_frame(FrameKind::Data, flags, stream_id, data, encoded);
+    }
+}
@@ -1,4 +1,4 @@
-use crate::request::{HttpHeader, HttpHeaderMap};
+use crate::request::{HttpHeader, HeaderMap};
 use crate::response::HttpResponse;
 
 pub trait HttpResponseFormatter {
@@ -12,7 +12,7 @@ impl DefaultHttpResponseFormatter {
         DefaultHttpResponseFormatter {}
     }
 
-    fn insert_header_if_not_present(&self, key: HttpHeader, value: &str, headers: &mut HttpHeaderMap) {
+    fn insert_header_if_not_present(&self, key: HttpHeader, value: &str, headers: &mut HeaderMap) {
         if !headers.contains_key(&key) {
             headers.insert(key, value.to_string());
         }
@@ -28,7 +28,15 @@ impl HttpResponseFormatter for DefaultHttpResponseFormatter {
         self.insert_header_if_not_present(HttpHeader::Date, &formatted_date, &mut headers);
         self.insert_header_if_not_present(HttpHeader::Server, "altaria", &mut headers);
         self.insert_header_if_not_present(HttpHeader::ContentType, "text/plain", &mut headers);
-        self.insert_header_if_not_present(HttpHeader::ContentLength, &response.body.len().to_string(), &mut headers);
+        match response.body.len() {
+            // There's no body to delimit, and announcing one is forbidden
+            _ if !response.status_code.allows_body() => {},
+            Some(length) => self.insert_header_if_not_present(HttpHeader::ContentLength, &length.to_string(), &mut headers),
+            None if !headers.contains_key(&HttpHeader::ContentLength) => {
+                self.insert_header_if_not_present(HttpHeader::TransferEncoding, "chunked", &mut headers)
+            },
+            None => {}
+        }
 
         HttpResponse {
             status_code: response.status_code,
@@ -36,4 +44,35 @@ impl HttpResponseFormatter for DefaultHttpResponseFormatter {
             body: response.body
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::response::HttpStatusCode;
+    use crate::response::body::HttpBody;
+
+    fn format(status_code: HttpStatusCode, headers: HeaderMap, body: HttpBody) -> HeaderMap {
+        DefaultHttpResponseFormatter::new().format(HttpResponse { status_code, headers, body }).headers
+    }
+
+    #[test]
+    fn custom_content_length_is_the_only_framing() {
+        let mut headers = HeaderMap::new();
+        headers.insert(HttpHeader::Custom("content-length".to_string()), "5");
+        let body = HttpBody::stream(futures::stream::iter(["hel", "lo"]));
+        let headers = format(HttpStatusCode::OK, headers, body);
+        assert_eq!(headers.get_all(&HttpHeader::ContentLength).collect::<Vec<_>>(), ["5"]);
+        assert!(!headers.contains_key(&HttpHeader::TransferEncoding));
+    }
+
+    #[test]
+    fn responses_without_a_body_are_not_framed() {
+        for status in [HttpStatusCode::Continue, HttpStatusCode::EarlyHints, HttpStatusCode::NoContent, HttpStatusCode::NotModified] {
+            for body in [HttpBody::from("hello"), HttpBody::stream(futures::stream::iter(["hello"]))] {
+                let headers = format(status, HeaderMap::new(), body);
+                assert!(!headers.contains_key(&HttpHeader::ContentLength), "{status:?}");
+                assert!(!headers.contains_key(&HttpHeader::TransferEncoding), "{status:?}");
+            }
+        }
+    }
+}
@@ -2,6 +2,7 @@ use thiserror::Error;
 
 pub mod format;
 pub mod alpha;
+pub mod beta;
 
 #[derive(Debug, Error)]
 pub enum HttpEncoderError {
@@ -2,6 +2,8 @@ pub mod state;
 pub mod param;
 pub mod req;
 pub mod query;
+#[cfg(unix)]
+pub mod peer;
 
 use async_trait::async_trait;
 use crate::request::HttpRequest;
@@ -18,5 +20,11 @@ pub enum ExtractorError {
     WrongProvidedFormat,
     UnregisteredExtension,
     BodyParseError,
-    UnexpectedContentType
+    UnexpectedContentType,
+    MissingPeerCertificate,
+    MissingPeerCredentials,
+    MissingListener,
+    MissingProxyHeader,
+    RequestTimeout,
+    BodyTooLarge
 }
\ No newline at end of file
@@ -1,9 +1,13 @@
 use std::str::FromStr;
 use async_trait::async_trait;
+use crate::extractor::query::NamedExtractor;
 use crate::extractor::{ExtractorError, FromRequest};
 use crate::request::HttpRequest;
 
 pub struct Param<T>(pub T);
+/// A path parameter from an optional segment, such as `{page?}`, which is `None` when the
+/// segment was left out.
+pub struct OptionalParam<T>(pub Option<T>);
 
 impl<T> Param<T> {
     pub fn new(value: T) -> Self {
@@ -29,3 +33,22 @@ impl<T> FromRequest for Param<T> where T : FromStr {
         ).map_err(|_| ExtractorError::WrongProvidedFormat)?))
     }
 }
+
+impl<T: FromStr> NamedExtractor for Param<T> {
+    fn from_request_by_name(name: &str, request: &HttpRequest) -> Result<Self, ExtractorError> {
+        match OptionalParam::<T>::from_request_by_name(name, request)? {
+            OptionalParam(Some(value)) => Ok(Param(value)),
+            OptionalParam(None) => Err(ExtractorError::UnregisteredPath)
+        }
+    }
+}
+
+impl<T: FromStr> NamedExtractor for OptionalParam<T> {
+    fn from_request_by_name(name: &str, request: &HttpRequest) -> Result<Self, ExtractorError> {
+        let path_values = request.path_values.get().ok_or(ExtractorError::UnregisteredPath)?;
+        match path_values.params.get(name) {
+            Some(value) => Ok(OptionalParam(Some(T::from_str(value).map_err(|_| ExtractorError::WrongProvidedFormat)?))),
+            None => Ok(OptionalParam(None))
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use async_trait::async_trait;
+use crate::extractor::{ExtractorError, FromRequest};
+use crate::request::HttpRequest;
+use tokio::net::unix::UCred;
+
+/// The process on the other end of a Unix socket, as reported by the kernel when the
+/// connection was accepted. Not available for TCP connections.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct PeerCredentials {
+    uid: u32,
+    gid: u32,
+    pid: Option<i32>
+}
+
+impl PeerCredentials {
+    pub fn uid(&self) -> u32 {
+        self.uid
+    }
+
+    pub fn gid(&self) -> u32 {
+        self.gid
+    }
+
+    /// Not every platform reports the process id.
+    pub fn pid(&self) -> Option<i32> {
+        self.pid
+    }
+}
+
+impl From<UCred> for PeerCredentials {
+    fn from(credentials: UCred) -> Self {
+        PeerCredentials {
+            uid: credentials.uid(),
+            gid: credentials.gid(),
+            pid: credentials.pid()
+        }
+    }
+}
+
+#[async_trait]
+impl FromRequest for PeerCredentials {
+    async fn from_request(_index: usize, request: &mut HttpRequest) -> Result<Self, ExtractorError>
+    where
+        Self: Sized
+    {
+        request.connection.peer_credentials.ok_or(ExtractorError::MissingPeerCredentials)
+    }
+}
+
+#[async_trait]
+impl FromRequest for Option<PeerCredentials> {
+    async fn from_request(_index: usize, request: &mut HttpRequest) -> Result<Self, ExtractorError>
+    where
+        Self: Sized
+    {
+        Ok(request.connection.peer_credentials)
+    }
+}
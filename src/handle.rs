@@ -0,0 +1,110 @@
+use crate::limits::{ConnectionStats, ConnectionTracker};
+use crate::listener::ListenerInfo;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+/// Lets a running server be shut down from elsewhere. Shutting down stops accepting new
+/// connections, and gives the open ones until the shutdown timeout to finish their requests.
+#[derive(Clone)]
+pub struct ServerHandle {
+    shutdown: Arc<watch::Sender<bool>>,
+    connections: Arc<ConnectionTracker>
+}
+
+impl ServerHandle {
+    pub fn new() -> Self {
+        ServerHandle {
+            shutdown: Arc::new(watch::Sender::new(false)),
+            connections: Arc::new(ConnectionTracker::default())
+        }
+    }
+
+    pub fn shutdown(&self) {
+        self.shutdown.send_replace(true);
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        *self.shutdown.borrow()
+    }
+
+    pub fn connection_stats(&self) -> ConnectionStats {
+        self.connections.stats()
+    }
+
+    /// How many connections are open from the given client IP.
+    pub fn active_connections_from(&self, ip: IpAddr) -> usize {
+        self.connections.active_from(ip)
+    }
+
+    pub(crate) fn signal(&self) -> ShutdownSignal {
+        ShutdownSignal(self.shutdown.subscribe())
+    }
+
+    pub(crate) fn tracker(&self) -> Arc<ConnectionTracker> {
+        self.connections.clone()
+    }
+}
+
+impl Default for ServerHandle {
+    fn default() -> Self {
+        ServerHandle::new()
+    }
+}
+
+/// A server running on its own task, as started by [Server::spawn](crate::Server::spawn).
+/// Dropping it leaves the server running.
+pub struct SpawnedServer {
+    pub(crate) handle: ServerHandle,
+    pub(crate) listeners: Vec<ListenerInfo>,
+    pub(crate) task: JoinHandle<crate::Result<()>>
+}
+
+impl SpawnedServer {
+    /// The address of the first TCP listener, which tells the port that was picked when
+    /// binding to port 0.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.listeners.iter().find_map(ListenerInfo::local_addr)
+    }
+
+    pub fn listeners(&self) -> &[ListenerInfo] {
+        &self.listeners
+    }
+
+    pub fn handle(&self) -> ServerHandle {
+        self.handle.clone()
+    }
+
+    pub fn shutdown(&self) {
+        self.handle.shutdown();
+    }
+
+    /// Waits for the server to stop, which happens once it has been shut down and its
+    /// connections are closed.
+    pub async fn join(self) -> crate::Result<()> {
+        self.task.await?
+    }
+}
+
+/// What connections watch to learn that the server is shutting down.
+#[derive(Clone)]
+pub(crate) struct ShutdownSignal(watch::Receiver<bool>);
+
+impl ShutdownSignal {
+    /// A signal for connections that aren't owned by a server, which is never triggered.
+    pub(crate) fn never() -> Self {
+        ShutdownSignal(watch::channel(false).1)
+    }
+
+    pub(crate) fn is_triggered(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Resolves once shutdown is triggered, or never if it can no longer be.
+    pub(crate) async fn triggered(&mut self) {
+        if self.0.wait_for(|triggered| *triggered).await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    }
+}
@@ -0,0 +1,349 @@
+use crate::hpack::table::{entry_size, DynamicTable};
+use crate::hpack::{decode_integer, huffman, HeaderField, HpackError, DEFAULT_TABLE_SIZE};
+
+/// Decodes header blocks, keeping the dynamic table in sync with the peer's encoder.
+/// Blocks must be decoded in the order they were received on the connection.
+pub struct Decoder {
+    table: DynamicTable,
+    max_table_size: usize,
+    max_list_size: usize,
+    max_fields: usize
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Decoder::with_max_table_size(DEFAULT_TABLE_SIZE)
+    }
+
+    /// Creates a decoder for a peer that was told the table may grow up to the given size,
+    /// through SETTINGS_HEADER_TABLE_SIZE.
+    pub fn with_max_table_size(max_table_size: usize) -> Self {
+        Decoder {
+            table: DynamicTable::new(max_table_size),
+            max_table_size,
+            max_list_size: usize::MAX,
+            max_fields: usize::MAX
+        }
+    }
+
+    /// Bounds the header lists decoded from then on, both by their size, which counts every
+    /// field as its name and value plus 32 octets, as SETTINGS_MAX_HEADER_LIST_SIZE does, and
+    /// by how many fields they have. Unbounded by default.
+    pub fn set_header_list_limits(&mut self, max_size: usize, max_fields: usize) {
+        self.max_list_size = max_size;
+        self.max_fields = max_fields;
+    }
+
+    /// Decodes a header block. A block whose header list goes over the limits still updates
+    /// the dynamic table as the peer's encoder did, so that later blocks can be decoded, but
+    /// fails with [HpackError::HeaderListTooLarge], keeping none of the fields past the limits.
+    pub fn decode(&mut self, block: &[u8]) -> Result<Vec<HeaderField>, HpackError> {
+        let mut headers = Vec::new();
+        let mut list = HeaderListBudget { size: 0, fields: 0, max_size: self.max_list_size, max_fields: self.max_fields };
+        let mut cursor = 0;
+
+        while cursor < block.len() {
+            let remaining = &block[cursor..];
+            let first = remaining[0];
+
+            let field = if first & 0x80 != 0 {
+                // Indexed header field, which is only copied once it's known to fit, since
+                // referencing it takes a single byte no matter how large it is
+                let (index, read) = decode_integer(remaining, 7)?;
+                let (name, value) = self.table.get(index).ok_or(HpackError::InvalidIndex)?;
+                cursor += read;
+                list.count(name, value).then(|| HeaderField::new(name, value))
+            } else if first & 0xc0 == 0x40 {
+                // Literal header field with incremental indexing
+                let (name, value, read) = self.decode_literal(remaining, 6)?;
+                self.table.insert(name.clone(), value.clone());
+                cursor += read;
+                list.count(&name, &value).then(|| HeaderField::new(name, value))
+            } else if first & 0xe0 == 0x20 {
+                // Dynamic table size updates are only allowed at the beginning of a block
+                if list.fields > 0 {
+                    return Err(HpackError::InvalidTableSizeUpdate);
+                }
+                let (size, read) = decode_integer(remaining, 5)?;
+                if size > self.max_table_size {
+                    return Err(HpackError::InvalidTableSizeUpdate);
+                }
+                self.table.set_max_size(size);
+                cursor += read;
+                None
+            } else {
+                // Literal header field without indexing or never indexed
+                let (name, value, read) = self.decode_literal(remaining, 4)?;
+                cursor += read;
+                list.count(&name, &value).then_some(HeaderField {
+                    name,
+                    value,
+                    sensitive: first & 0xf0 == 0x10
+                })
+            };
+            headers.extend(field);
+        }
+
+        if list.exceeded() {
+            return Err(HpackError::HeaderListTooLarge);
+        }
+        Ok(headers)
+    }
+
+    fn decode_literal(&self, bytes: &[u8], prefix_bits: u8) -> Result<(String, String, usize), HpackError> {
+        let (index, mut read) = decode_integer(bytes, prefix_bits)?;
+        let name = if index == 0 {
+            let (name, name_read) = decode_string(&bytes[read..])?;
+            read += name_read;
+            name
+        } else {
+            self.table.get(index).ok_or(HpackError::InvalidIndex)?.0.to_string()
+        };
+        let (value, value_read) = decode_string(&bytes[read..])?;
+        Ok((name, value, read + value_read))
+    }
+}
+
+/// How much of the limits the header list being decoded takes up so far.
+struct HeaderListBudget {
+    size: usize,
+    fields: usize,
+    max_size: usize,
+    max_fields: usize
+}
+
+impl HeaderListBudget {
+    /// Counts a field, returning whether the list is still within the limits.
+    fn count(&mut self, name: &str, value: &str) -> bool {
+        self.size = self.size.saturating_add(entry_size(name, value));
+        self.fields += 1;
+        !self.exceeded()
+    }
+
+    fn exceeded(&self) -> bool {
+        self.size > self.max_size || self.fields > self.max_fields
+    }
+}
+
+fn decode_string(bytes: &[u8]) -> Result<(String, usize), HpackError> {
+    let huffman_encoded = bytes.first().ok_or(HpackError::UnexpectedEnd)? & 0x80 != 0;
+    let (length, read) = decode_integer(bytes, 7)?;
+    let end = read.checked_add(length).ok_or(HpackError::IntegerOverflow)?;
+    let raw = bytes.get(read..end).ok_or(HpackError::UnexpectedEnd)?;
+
+    let decoded = if huffman_encoded { huffman::decode(raw)? } else { raw.to_vec() };
+    let string = String::from_utf8(decoded).map_err(|_| HpackError::InvalidUtf8)?;
+    Ok((string, end))
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Decoder::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hpack::table::STATIC_TABLE;
+    use crate::hpack::tests::hex;
+
+    fn fields(fields: &[(&str, &str)]) -> Vec<HeaderField> {
+        fields.iter().map(|(name, value)| HeaderField::new(*name, *value)).collect()
+    }
+
+    /// The dynamic table from the most recent entry on, along with its size.
+    fn dynamic_table(decoder: &Decoder) -> (Vec<(&str, &str)>, usize) {
+        let entries: Vec<(&str, &str)> = (STATIC_TABLE.len() + 1..)
+            .map_while(|index| decoder.table.get(index))
+            .collect();
+        let size = entries.iter().map(|(name, value)| entry_size(name, value)).sum();
+        (entries, size)
+    }
+
+    #[test]
+    fn literal_with_indexing_of_appendix_c21() {
+        let mut decoder = Decoder::new();
+        let block = hex("400a 6375 7374 6f6d 2d6b 6579 0d63 7573 746f 6d2d 6865 6164 6572");
+        assert_eq!(decoder.decode(&block), Ok(fields(&[("custom-key", "custom-header")])));
+        assert_eq!(dynamic_table(&decoder), (vec![("custom-key", "custom-header")], 55));
+    }
+
+    #[test]
+    fn literal_without_indexing_of_appendix_c22() {
+        let mut decoder = Decoder::new();
+        let block = hex("040c 2f73 616d 706c 652f 7061 7468");
+        assert_eq!(decoder.decode(&block), Ok(fields(&[(":path", "/sample/path")])));
+        assert_eq!(dynamic_table(&decoder), (vec![], 0));
+    }
+
+    #[test]
+    fn literal_never_indexed_of_appendix_c23() {
+        let mut decoder = Decoder::new();
+        let block = hex("1008 7061 7373 776f 7264 0673 6563 7265 74");
+        assert_eq!(decoder.decode(&block), Ok(vec![HeaderField::sensitive("password", "secret")]));
+        assert_eq!(dynamic_table(&decoder), (vec![], 0));
+    }
+
+    #[test]
+    fn indexed_field_of_appendix_c24() {
+        let mut decoder = Decoder::new();
+        assert_eq!(decoder.decode(&hex("82")), Ok(fields(&[(":method", "GET")])));
+        assert_eq!(dynamic_table(&decoder), (vec![], 0));
+    }
+
+    /// The requests of C.3 and C.4, which only differ in whether strings are Huffman-encoded.
+    fn decode_request_sequence(blocks: [&str; 3]) {
+        let mut decoder = Decoder::new();
+        assert_eq!(decoder.decode(&hex(blocks[0])), Ok(fields(&[
+            (":method", "GET"),
+            (":scheme", "http"),
+            (":path", "/"),
+            (":authority", "www.example.com")
+        ])));
+        assert_eq!(dynamic_table(&decoder), (vec![(":authority", "www.example.com")], 57));
+
+        assert_eq!(decoder.decode(&hex(blocks[1])), Ok(fields(&[
+            (":method", "GET"),
+            (":scheme", "http"),
+            (":path", "/"),
+            (":authority", "www.example.com"),
+            ("cache-control", "no-cache")
+        ])));
+        assert_eq!(dynamic_table(&decoder), (vec![
+            ("cache-control", "no-cache"),
+            (":authority", "www.example.com")
+        ], 110));
+
+        assert_eq!(decoder.decode(&hex(blocks[2])), Ok(fields(&[
+            (":method", "GET"),
+            (":scheme", "https"),
+            (":path", "/index.html"),
+            (":authority", "www.example.com"),
+            ("custom-key", "custom-value")
+        ])));
+        assert_eq!(dynamic_table(&decoder), (vec![
+            ("custom-key", "custom-value"),
+            ("cache-control", "no-cache"),
+            (":authority", "www.example.com")
+        ], 164));
+    }
+
+    #[test]
+    fn requests_without_huffman_of_appendix_c3() {
+        decode_request_sequence([
+            "8286 8441 0f77 7777 2e65 7861 6d70 6c65 2e63 6f6d",
+            "8286 84be 5808 6e6f 2d63 6163 6865",
+            "8287 85bf 400a 6375 7374 6f6d 2d6b 6579 0c63 7573 746f 6d2d 7661 6c75 65"
+        ]);
+    }
+
+    #[test]
+    fn requests_with_huffman_of_appendix_c4() {
+        decode_request_sequence([
+            "8286 8441 8cf1 e3c2 e5f2 3a6b a0ab 90f4 ff",
+            "8286 84be 5886 a8eb 1064 9cbf",
+            "8287 85bf 4088 25a8 49e9 5ba9 7d7f 8925 a849 e95b b8e8 b4bf"
+        ]);
+    }
+
+    /// The responses of C.5 and C.6, decoded with a 256-octet table so that entries get
+    /// evicted along the way.
+    fn decode_response_sequence(blocks: [&str; 3]) {
+        let mut decoder = Decoder::with_max_table_size(256);
+        let location = ("location", "https://www.example.com");
+        let date = ("date", "Mon, 21 Oct 2013 20:13:21 GMT");
+
+        assert_eq!(decoder.decode(&hex(blocks[0])), Ok(fields(&[
+            (":status", "302"),
+            ("cache-control", "private"),
+            date,
+            location
+        ])));
+        assert_eq!(dynamic_table(&decoder), (vec![
+            location,
+            date,
+            ("cache-control", "private"),
+            (":status", "302")
+        ], 222));
+
+        // Adding `:status: 307` evicts `:status: 302`
+        assert_eq!(decoder.decode(&hex(blocks[1])), Ok(fields(&[
+            (":status", "307"),
+            ("cache-control", "private"),
+            date,
+            location
+        ])));
+        assert_eq!(dynamic_table(&decoder), (vec![
+            (":status", "307"),
+            location,
+            date,
+            ("cache-control", "private")
+        ], 222));
+
+        let later_date = ("date", "Mon, 21 Oct 2013 20:13:22 GMT");
+        let cookie = ("set-cookie", "foo=ASDJKHQKBZXOQWEOPIUAXQWEOIU; max-age=3600; version=1");
+        assert_eq!(decoder.decode(&hex(blocks[2])), Ok(fields(&[
+            (":status", "200"),
+            ("cache-control", "private"),
+            later_date,
+            location,
+            ("content-encoding", "gzip"),
+            cookie
+        ])));
+        assert_eq!(dynamic_table(&decoder), (vec![
+            cookie,
+            ("content-encoding", "gzip"),
+            later_date
+        ], 215));
+    }
+
+    #[test]
+    fn responses_without_huffman_of_appendix_c5() {
+        decode_response_sequence([
+            "4803 3330 3258 0770 7269 7661 7465 611d 4d6f 6e2c 2032 3120 4f63 7420 3230 3133
+             2032 303a 3133 3a32 3120 474d 546e 1768 7474 7073 3a2f 2f77 7777 2e65 7861 6d70
+             6c65 2e63 6f6d",
+            "4803 3330 37c1 c0bf",
+            "88c1 611d 4d6f 6e2c 2032 3120 4f63 7420 3230 3133 2032 303a 3133 3a32 3220 474d
+             54c0 5a04 677a 6970 7738 666f 6f3d 4153 444a 4b48 514b 425a 584f 5157 454f 5049
+             5541 5851 5745 4f49 553b 206d 6178 2d61 6765 3d33 3630 303b 2076 6572 7369 6f6e
+             3d31"
+        ]);
+    }
+
+    #[test]
+    fn responses_with_huffman_of_appendix_c6() {
+        decode_response_sequence([
+            "4882 6402 5885 aec3 771a 4b61 96d0 7abe 9410 54d4 44a8 2005 9504 0b81 66e0 82a6
+             2d1b ff6e 919d 29ad 1718 63c7 8f0b 97c8 e9ae 82ae 43d3",
+            "4883 640e ffc1 c0bf",
+            "88c1 6196 d07a be94 1054 d444 a820 0595 040b 8166 e084 a62d 1bff c05a 839b d9ab
+             77ad 94e7 821d d7f2 e6c7 b335 dfdf cd5b 3960 d5af 2708 7f36 72c1 ab27 0fb5 291f
+             9587 3160 65c0 03ed 4ee5 b106 3d50 07"
+        ]);
+    }
+
+    #[test]
+    fn size_update_evicts_entries_that_no_longer_fit() {
+        let mut decoder = Decoder::new();
+        decoder.decode(&hex("8286 8441 0f77 7777 2e65 7861 6d70 6c65 2e63 6f6d")).unwrap();
+        decoder.decode(&hex("8286 84be 5808 6e6f 2d63 6163 6865")).unwrap();
+
+        // Shrinking the table to 64 octets only leaves room for the newest entry
+        assert_eq!(decoder.decode(&hex("3f21 82")), Ok(fields(&[(":method", "GET")])));
+        assert_eq!(dynamic_table(&decoder), (vec![("cache-control", "no-cache")], 53));
+
+        // Emptying the table evicts everything, and the peer may grow it back afterwards
+        assert_eq!(decoder.decode(&hex("20 3fe1 1f")), Ok(vec![]));
+        assert_eq!(dynamic_table(&decoder), (vec![], 0));
+        assert_eq!(decoder.decode(&hex("be")), Err(HpackError::InvalidIndex));
+    }
+
+    #[test]
+    fn misplaced_or_oversized_size_update_is_rejected() {
+        let mut decoder = Decoder::with_max_table_size(256);
+        assert_eq!(decoder.decode(&hex("82 3f21")), Err(HpackError::InvalidTableSizeUpdate));
+        assert_eq!(decoder.decode(&hex("3fe2 01")), Err(HpackError::InvalidTableSizeUpdate));
+    }
+}
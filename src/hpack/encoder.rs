@@ -0,0 +1,184 @@
+use crate::hpack::table::DynamicTable;
+use crate::hpack::{encode_integer, huffman, HeaderField, DEFAULT_TABLE_SIZE};
+
+/// Encodes header blocks, adding fields to the dynamic table so that repeated ones shrink
+/// to a single byte. Blocks must be sent in the order they were encoded.
+pub struct Encoder {
+    table: DynamicTable,
+    /// The smallest and the latest table size since the last block, both of which the
+    /// peer must be told about (RFC 7541 section 4.2)
+    pending_size_update: Option<(usize, usize)>
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Encoder {
+            table: DynamicTable::new(DEFAULT_TABLE_SIZE),
+            pending_size_update: None
+        }
+    }
+
+    /// Applies the peer's SETTINGS_HEADER_TABLE_SIZE. The table never grows past the default
+    /// size, however large the peer allows it to be.
+    pub fn set_max_table_size(&mut self, max_table_size: usize) {
+        let max_table_size = max_table_size.min(DEFAULT_TABLE_SIZE);
+        let smallest = match self.pending_size_update {
+            Some((smallest, _)) => smallest.min(max_table_size),
+            None if max_table_size == self.table.max_size() => return,
+            None => max_table_size
+        };
+        self.pending_size_update = Some((smallest, max_table_size));
+        self.table.set_max_size(max_table_size);
+    }
+
+    pub fn encode<'a, I>(&mut self, fields: I) -> Vec<u8>
+    where I: IntoIterator<Item = &'a HeaderField> {
+        let mut encoded = Vec::new();
+        if let Some((smallest, latest)) = self.pending_size_update.take() {
+            if smallest < latest {
+                encode_integer(smallest, 5, 0x20, &mut encoded);
+            }
+            encode_integer(latest, 5, 0x20, &mut encoded);
+        }
+
+        for field in fields {
+            self.encode_field(field, &mut encoded);
+        }
+        encoded
+    }
+
+    fn encode_field(&mut self, field: &HeaderField, encoded: &mut Vec<u8>) {
+        let found = self.table.find(&field.name, &field.value);
+        if let (Some((index, true)), false) = (found, field.sensitive) {
+            // Indexed header field
+            encode_integer(index, 7, 0x80, encoded);
+            return;
+        }
+
+        let name_index = found.map_or(0, |(index, _)| index);
+        if field.sensitive {
+            // Literal header field never indexed
+            encode_integer(name_index, 4, 0x10, encoded);
+        } else if self.table.fits(&field.name, &field.value) {
+            // Literal header field with incremental indexing
+            encode_integer(name_index, 6, 0x40, encoded);
+            self.table.insert(field.name.clone(), field.value.clone());
+        } else {
+            // Literal header field without indexing, since the entry would just empty the table
+            encode_integer(name_index, 4, 0x00, encoded);
+        }
+
+        if name_index == 0 {
+            encode_string(&field.name, encoded);
+        }
+        encode_string(&field.value, encoded);
+    }
+}
+
+impl Default for Encoder {
+    fn default() -> Self {
+        Encoder::new()
+    }
+}
+
+/// Encodes a string literal, Huffman-encoding it only when that makes it shorter.
+fn encode_string(string: &str, encoded: &mut Vec<u8>) {
+    let huffman_len = huffman::encoded_len(string.as_bytes());
+    if huffman_len < string.len() {
+        encode_integer(huffman_len, 7, 0x80, encoded);
+        huffman::encode(string.as_bytes(), encoded);
+    } else {
+        encode_integer(string.len(), 7, 0x00, encoded);
+        encoded.extend_from_slice(string.as_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hpack::tests::hex;
+    use crate::hpack::Decoder;
+
+    fn fields(fields: &[(&str, &str)]) -> Vec<HeaderField> {
+        fields.iter().map(|(name, value)| HeaderField::new(*name, *value)).collect()
+    }
+
+    #[test]
+    fn requests_of_appendix_c4() {
+        let mut encoder = Encoder::new();
+        let first = fields(&[(":method", "GET"), (":scheme", "http"), (":path", "/"), (":authority", "www.example.com")]);
+        assert_eq!(encoder.encode(&first), hex("8286 8441 8cf1 e3c2 e5f2 3a6b a0ab 90f4 ff"));
+
+        let second = fields(&[
+            (":method", "GET"),
+            (":scheme", "http"),
+            (":path", "/"),
+            (":authority", "www.example.com"),
+            ("cache-control", "no-cache")
+        ]);
+        assert_eq!(encoder.encode(&second), hex("8286 84be 5886 a8eb 1064 9cbf"));
+
+        let third = fields(&[
+            (":method", "GET"),
+            (":scheme", "https"),
+            (":path", "/index.html"),
+            (":authority", "www.example.com"),
+            ("custom-key", "custom-value")
+        ]);
+        assert_eq!(encoder.encode(&third), hex("8287 85bf 4088 25a8 49e9 5ba9 7d7f 8925 a849 e95b b8e8 b4bf"));
+    }
+
+    #[test]
+    fn responses_of_appendix_c6_after_a_size_update() {
+        let mut encoder = Encoder::new();
+        encoder.set_max_table_size(256);
+        let date = ("date", "Mon, 21 Oct 2013 20:13:21 GMT");
+        let location = ("location", "https://www.example.com");
+
+        // The first block tells the peer about the smaller table before anything else
+        let first = fields(&[(":status", "302"), ("cache-control", "private"), date, location]);
+        let mut expected = hex("3fe1 01");
+        expected.extend(hex(
+            "4882 6402 5885 aec3 771a 4b61 96d0 7abe 9410 54d4 44a8 2005 9504 0b81 66e0 82a6
+             2d1b ff6e 919d 29ad 1718 63c7 8f0b 97c8 e9ae 82ae 43d3"
+        ));
+        assert_eq!(encoder.encode(&first), expected);
+
+        // "307" takes as many octets Huffman-encoded, so it's sent as is, like in C.5.2
+        let second = fields(&[(":status", "307"), ("cache-control", "private"), date, location]);
+        assert_eq!(encoder.encode(&second), hex("4803 3330 37c1 c0bf"));
+
+        let third = fields(&[
+            (":status", "200"),
+            ("cache-control", "private"),
+            ("date", "Mon, 21 Oct 2013 20:13:22 GMT"),
+            location,
+            ("content-encoding", "gzip"),
+            ("set-cookie", "foo=ASDJKHQKBZXOQWEOPIUAXQWEOIU; max-age=3600; version=1")
+        ]);
+        assert_eq!(encoder.encode(&third), hex(
+            "88c1 6196 d07a be94 1054 d444 a820 0595 040b 8166 e084 a62d 1bff c05a 839b d9ab
+             77ad 94e7 821d d7f2 e6c7 b335 dfdf cd5b 3960 d5af 2708 7f36 72c1 ab27 0fb5 291f
+             9587 3160 65c0 03ed 4ee5 b106 3d50 07"
+        ));
+    }
+
+    #[test]
+    fn shrinking_and_growing_the_table_sends_both_sizes() {
+        let mut encoder = Encoder::new();
+        encoder.set_max_table_size(0);
+        encoder.set_max_table_size(256);
+        assert_eq!(encoder.encode(&fields(&[(":method", "GET")])), hex("20 3fe1 01 82"));
+        assert_eq!(encoder.encode(&fields(&[(":method", "GET")])), hex("82"));
+    }
+
+    #[test]
+    fn sensitive_field_is_never_indexed() {
+        let mut encoder = Encoder::new();
+        let password = [HeaderField::sensitive("password", "secret")];
+        let encoded = encoder.encode(&password);
+        assert_eq!(encoded[0] & 0xf0, 0x10);
+        assert_eq!(encoder.encode(&password), encoded);
+        assert_eq!(Decoder::new().decode(&encoded), Ok(password.to_vec()));
+    }
+}
@@ -0,0 +1,176 @@
+//! HPACK, the header compression format of HTTP/2 (RFC 7541). Decoders and encoders
+//! keep a dynamic table each, so every connection needs its own pair.
+
+use crate::request::HttpHeader;
+use thiserror::Error;
+
+mod huffman;
+mod table;
+mod decoder;
+mod encoder;
+
+pub use decoder::Decoder;
+pub use encoder::Encoder;
+
+/// The table size both sides start with, until SETTINGS_HEADER_TABLE_SIZE says otherwise.
+pub const DEFAULT_TABLE_SIZE: usize = 4096;
+
+#[derive(Debug, Clone, Eq, PartialEq, Error)]
+pub enum HpackError {
+    #[error("The header block ended in the middle of a representation")]
+    UnexpectedEnd,
+    #[error("An integer exceeded the supported range")]
+    IntegerOverflow,
+    #[error("A field referred to an index outside of both tables")]
+    InvalidIndex,
+    #[error("A string contained an invalid Huffman code")]
+    InvalidHuffmanCode,
+    #[error("A Huffman-encoded string was not padded with the EOS prefix")]
+    InvalidHuffmanPadding,
+    #[error("A dynamic table size update was misplaced or exceeded the allowed size")]
+    InvalidTableSizeUpdate,
+    #[error("A field was not valid UTF-8")]
+    InvalidUtf8,
+    #[error("The decoded header list exceeded the allowed size or number of fields")]
+    HeaderListTooLarge
+}
+
+/// A single field of a header block.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct HeaderField {
+    pub name: String,
+    pub value: String,
+    /// Sensitive fields are never added to a compression table, not even by intermediaries
+    /// that re-encode them, so that their values can't be guessed from the compression ratio.
+    pub sensitive: bool
+}
+
+impl HeaderField {
+    pub fn new(name: impl Into<String>, value: impl Into<String>) -> Self {
+        HeaderField {
+            name: name.into(),
+            value: value.into(),
+            sensitive: false
+        }
+    }
+
+    pub fn sensitive(name: impl Into<String>, value: impl Into<String>) -> Self {
+        HeaderField {
+            sensitive: true,
+            ..HeaderField::new(name, value)
+        }
+    }
+
+    pub fn header(&self) -> HttpHeader {
+        HttpHeader::from_name(&self.name)
+    }
+}
+
+impl From<(HttpHeader, String)> for HeaderField {
+    /// Lowercases the name, as HTTP/2 requires, and marks credentials as sensitive. Short
+    /// cookies are too, since they're the easiest to guess (RFC 7541 section 7.1.3).
+    fn from((header, value): (HttpHeader, String)) -> Self {
+        let sensitive = match header {
+            HttpHeader::Authorization | HttpHeader::ProxyAuthorization => true,
+            HttpHeader::Cookie => value.len() < 20,
+            _ => false
+        };
+        HeaderField {
+            name: header.name().to_lowercase(),
+            value,
+            sensitive
+        }
+    }
+}
+
+impl From<HeaderField> for (HttpHeader, String) {
+    fn from(field: HeaderField) -> Self {
+        (field.header(), field.value)
+    }
+}
+
+/// Decodes an integer with an N-bit prefix (RFC 7541 section 5.1), returning it along
+/// with the amount of bytes it took.
+pub(crate) fn decode_integer(bytes: &[u8], prefix_bits: u8) -> Result<(usize, usize), HpackError> {
+    let mask = (1u16 << prefix_bits) as usize - 1;
+    let first = *bytes.first().ok_or(HpackError::UnexpectedEnd)? as usize & mask;
+    if first < mask {
+        return Ok((first, 1));
+    }
+
+    let mut value = mask;
+    let mut shift = 0;
+    for (index, byte) in bytes.iter().enumerate().skip(1) {
+        // Anything past 28 bits of continuation is far beyond any sensible length or index
+        if shift > 28 {
+            return Err(HpackError::IntegerOverflow);
+        }
+        value += ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Ok((value, index + 1));
+        }
+        shift += 7;
+    }
+    Err(HpackError::UnexpectedEnd)
+}
+
+/// Encodes an integer with an N-bit prefix, keeping the bits above the prefix in the
+/// first byte as given by `flags`.
+pub(crate) fn encode_integer(value: usize, prefix_bits: u8, flags: u8, encoded: &mut Vec<u8>) {
+    let mask = (1u16 << prefix_bits) as usize - 1;
+    if value < mask {
+        encoded.push(flags | value as u8);
+        return;
+    }
+
+    encoded.push(flags | mask as u8);
+    let mut remaining = value - mask;
+    while remaining >= 0x80 {
+        encoded.push((remaining & 0x7f) as u8 | 0x80);
+        remaining >>= 7;
+    }
+    encoded.push(remaining as u8);
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Parses the hex dumps of RFC 7541 Appendix C, ignoring whitespace.
+    pub(crate) fn hex(dump: &str) -> Vec<u8> {
+        let digits: Vec<u8> = dump.bytes().filter(|byte| !byte.is_ascii_whitespace()).collect();
+        digits.chunks(2)
+            .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).unwrap(), 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn integers_of_appendix_c1() {
+        let cases: [(usize, u8, &[u8]); 3] = [
+            // C.1.1, C.1.2 and C.1.3
+            (10, 5, &[0x0a]),
+            (1337, 5, &[0x1f, 0x9a, 0x0a]),
+            (42, 8, &[0x2a])
+        ];
+        for (value, prefix_bits, bytes) in cases {
+            let mut encoded = Vec::new();
+            encode_integer(value, prefix_bits, 0, &mut encoded);
+            assert_eq!(encoded, bytes);
+            assert_eq!(decode_integer(bytes, prefix_bits), Ok((value, bytes.len())));
+        }
+    }
+
+    #[test]
+    fn integer_keeps_the_flags_above_its_prefix() {
+        let mut encoded = Vec::new();
+        encode_integer(1337, 5, 0xe0, &mut encoded);
+        assert_eq!(encoded, [0xff, 0x9a, 0x0a]);
+        assert_eq!(decode_integer(&encoded, 5), Ok((1337, 3)));
+    }
+
+    #[test]
+    fn truncated_integer_is_rejected() {
+        assert_eq!(decode_integer(&[0x1f, 0x9a], 5), Err(HpackError::UnexpectedEnd));
+        assert_eq!(decode_integer(&[0x1f, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01], 5), Err(HpackError::IntegerOverflow));
+    }
+}
@@ -0,0 +1,157 @@
+use std::collections::VecDeque;
+
+/// The static table from RFC 7541 Appendix A. Indices are 1-based on the wire.
+pub(crate) const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+/// Every entry accounts for its name, its value and an overhead of 32 octets.
+const ENTRY_OVERHEAD: usize = 32;
+
+/// The size of a field in the dynamic table, which is also how it counts towards the size
+/// of a header list (RFC 7541 section 4.1).
+pub(crate) fn entry_size(name: &str, value: &str) -> usize {
+    name.len() + value.len() + ENTRY_OVERHEAD
+}
+
+/// The dynamic table, where the most recently inserted entry has the lowest index.
+#[derive(Debug, Clone)]
+pub(crate) struct DynamicTable {
+    entries: VecDeque<(String, String)>,
+    size: usize,
+    max_size: usize
+}
+
+impl DynamicTable {
+    pub(crate) fn new(max_size: usize) -> Self {
+        DynamicTable {
+            entries: VecDeque::new(),
+            size: 0,
+            max_size
+        }
+    }
+
+    pub(crate) fn set_max_size(&mut self, max_size: usize) {
+        self.max_size = max_size;
+        self.evict(0);
+    }
+
+    /// Looks up an entry by its wire index, which counts the static table first.
+    pub(crate) fn get(&self, index: usize) -> Option<(&str, &str)> {
+        match index {
+            0 => None,
+            index if index <= STATIC_TABLE.len() => Some(STATIC_TABLE[index - 1]),
+            index => self.entries
+                .get(index - STATIC_TABLE.len() - 1)
+                .map(|(name, value)| (name.as_str(), value.as_str()))
+        }
+    }
+
+    /// Looks for the best entry to encode a field with, returning its wire index and whether
+    /// its value matches as well, or only its name.
+    pub(crate) fn find(&self, name: &str, value: &str) -> Option<(usize, bool)> {
+        let static_entries = STATIC_TABLE.iter().map(|&(name, value)| (name, value));
+        let dynamic_entries = self.entries.iter().map(|(name, value)| (name.as_str(), value.as_str()));
+
+        let mut name_match = None;
+        for (index, (entry_name, entry_value)) in static_entries.chain(dynamic_entries).enumerate() {
+            if entry_name != name {
+                continue;
+            }
+            if entry_value == value {
+                return Some((index + 1, true));
+            }
+            name_match.get_or_insert(index + 1);
+        }
+        name_match.map(|index| (index, false))
+    }
+
+    pub(crate) fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    /// Whether an entry could fit in the table at all.
+    pub(crate) fn fits(&self, name: &str, value: &str) -> bool {
+        entry_size(name, value) <= self.max_size
+    }
+
+    /// Inserts an entry, evicting the oldest ones to make room. An entry larger than
+    /// the whole table just empties it.
+    pub(crate) fn insert(&mut self, name: String, value: String) {
+        let size = entry_size(&name, &value);
+        self.evict(size);
+        if size <= self.max_size {
+            self.size += size;
+            self.entries.push_front((name, value));
+        }
+    }
+
+    fn evict(&mut self, incoming: usize) {
+        while self.size + incoming > self.max_size {
+            match self.entries.pop_back() {
+                Some((name, value)) => self.size -= entry_size(&name, &value),
+                None => break
+            }
+        }
+    }
+}
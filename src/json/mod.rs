@@ -1,6 +1,7 @@
 use async_trait::async_trait;
 use crate::extractor::{ExtractorError, FromRequest};
 use crate::headers;
+use crate::parser::body::BodyError;
 use crate::request::{ContentType, HttpRequest};
 use crate::response::into::IntoResponse;
 use crate::response::{HttpResponse, HttpStatusCode};
@@ -19,7 +20,11 @@ where
             return Err(ExtractorError::UnexpectedContentType);
         }
 
-        let body = request.read_body().await;
+        let body = request.read_body().await.map_err(|error| match error {
+            BodyError::TimedOut => ExtractorError::RequestTimeout,
+            BodyError::TooLarge => ExtractorError::BodyTooLarge,
+            _ => ExtractorError::BodyParseError
+        })?;
         let value = serde_json::from_slice(&*body).map_err(|err| ExtractorError::BodyParseError )?;
         Ok(JsonBody(value))
     }
@@ -33,7 +38,7 @@ impl<T : Serialize> IntoResponse for JsonBody<T> {
                 headers: headers! {
                     ContentType: "application/json"
                 },
-                body
+                body: body.into()
             },
             Err(e) => panic!("Failed to serialize response: {}", e)
         }
@@ -10,14 +10,39 @@ pub mod middleware;
 #[cfg(feature = "json")]
 pub mod json;
 mod util;
+mod handle;
+mod timeouts;
+pub mod hpack;
+pub mod listener;
+pub mod proxy;
+pub mod limits;
+#[cfg(feature = "tls")]
+pub mod tls;
 
+use crate::encoder::alpha::AlphaHttpEncoder;
+use crate::encoder::format::DefaultHttpResponseFormatter;
+use crate::parser::alpha::AlphaHttpParser;
+use crate::handle::ShutdownSignal;
+use crate::listener::ListenerConfig;
+use crate::protocol::alpha::AlphaConnectionContext;
+use crate::protocol::stream::ConnectionStream;
 use crate::protocol::HttpProtocol;
+use crate::request::PeerAddr;
 use crate::router::{Router};
 use std::net::{Ipv4Addr, SocketAddr};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
 use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+pub use handle::{ServerHandle, SpawnedServer};
+pub use timeouts::Timeouts;
 
 type Result<T> = anyhow::Result<T>;
 
+const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
 pub struct HttpServer {
     pub protocol: Box<dyn HttpProtocol>
 }
@@ -29,24 +54,93 @@ impl HttpServer {
         }
     }
 
-    // pub fn http2() -> HttpServer {
-    //     HttpServer {
-    //         protocol: Box::new(protocol::beta::BetaHttpProtocol::new())
-    //     }
-    // }
+    pub fn http2(router: Router) -> HttpServer {
+        HttpServer {
+            protocol: Box::new(protocol::beta::BetaHttpProtocol::link_router(router))
+        }
+    }
 
     pub fn set_router(&mut self, router: Router) {
         self.protocol.set_router(router)
     }
 
+    pub fn set_timeouts(&mut self, timeouts: Timeouts) {
+        self.protocol.set_timeouts(timeouts)
+    }
+
+    pub fn set_connection_limits(&mut self, limits: limits::ConnectionLimits) {
+        self.protocol.set_connection_limits(limits)
+    }
+
+    pub fn set_parser_limits(&mut self, limits: limits::ParserLimits) {
+        self.protocol.set_parser_limits(limits)
+    }
+
+    pub fn set_error_handler(&mut self, handler: response::error::ErrorHandler) {
+        self.protocol.set_error_handler(handler)
+    }
+
+    /// Terminates TLS on every accepted connection, loading the certificate right away.
+    #[cfg(feature = "tls")]
+    pub fn set_tls(&mut self, config: tls::TlsConfig) -> Result<()> {
+        self.protocol.set_tls(config)
+    }
+
     pub async fn bind(&mut self, addr: &str) -> Result<&mut Self> {
         self.protocol.connect(addr).await.map(|_| self)
     }
 
+    /// Listens on a Unix socket, setting the file's permission bits to `mode` when given.
+    /// A socket file left behind by a server that is no longer running is replaced.
+    #[cfg(unix)]
+    pub async fn bind_unix<P: Into<std::path::PathBuf>>(&mut self, path: P, mode: Option<u32>) -> Result<&mut Self> {
+        let mut config = ListenerConfig::unix(path);
+        if let Some(mode) = mode {
+            config = config.permissions(mode);
+        }
+        self.add_listener(config).await
+    }
+
+    /// Binds one more listener, so that the same router is served on all of them at once.
+    pub async fn add_listener(&mut self, config: ListenerConfig) -> Result<&mut Self> {
+        self.protocol.add_listener(config).await.map(|_| self)
+    }
+
+    pub fn listeners(&self) -> Vec<listener::ListenerInfo> {
+        self.protocol.listeners()
+    }
+
+    /// Serves connections for as long as the server runs.
     pub async fn listen(self) -> Result<()> {
-        let static_ref = Box::leak(self.protocol);
-        static_ref.listen().await
+        self.listen_with_handle(&ServerHandle::new(), DEFAULT_SHUTDOWN_TIMEOUT).await
     }
+
+    /// Serves connections until the handle is told to shut down, giving the open ones up to
+    /// `shutdown_timeout` to finish their requests before returning.
+    pub async fn listen_with_handle(self, handle: &ServerHandle, shutdown_timeout: Duration) -> Result<()> {
+        self.protocol.listen(handle.clone(), shutdown_timeout).await
+    }
+}
+
+/// Serves requests from an already established connection, such as an in-memory pipe or a
+/// socket accepted elsewhere, until it's closed. Requests are read as HTTP/1.1, unless the
+/// client starts with the HTTP/2 preface or upgrades to h2c. Since nothing is known about
+/// the other end, requests report an unknown peer address. The default [Timeouts] apply.
+pub async fn serve_connection<I>(io: I, router: Arc<Router>)
+where I: AsyncRead + AsyncWrite + Unpin + Send + 'static {
+    let stream = ConnectionStream::Transport(Box::new(io));
+    let connection = Arc::new(stream.info(PeerAddr::Unknown, None));
+    let context = AlphaConnectionContext {
+        connection,
+        router,
+        parser: Arc::new(AlphaHttpParser::new()),
+        encoder: Arc::new(AlphaHttpEncoder::new()),
+        formatter: Arc::new(Box::new(DefaultHttpResponseFormatter::new())),
+        shutdown: ShutdownSignal::never(),
+        timeouts: Timeouts::default(),
+        error_handler: response::error::default_error_handler()
+    };
+    protocol::alpha::serve_stream(stream, context).await
 }
 
 impl Default for HttpServer {
@@ -58,8 +152,16 @@ impl Default for HttpServer {
 }
 
 pub struct Server {
-    address: Option<SocketAddr>,
-    router: Option<Router>
+    listeners: Vec<ListenerConfig>,
+    router: Option<Router>,
+    handle: ServerHandle,
+    shutdown_timeout: Duration,
+    timeouts: Timeouts,
+    limits: limits::ConnectionLimits,
+    parser_limits: limits::ParserLimits,
+    error_handler: Option<response::error::ErrorHandler>,
+    #[cfg(feature = "tls")]
+    tls: Option<tls::TlsConfig>
 }
 
 #[derive(Debug, Error)]
@@ -71,8 +173,16 @@ pub enum ServerBuildError {
 impl Server {
     pub fn builder() -> Self {
         Server {
-            address: None,
-            router: None
+            listeners: Vec::new(),
+            router: None,
+            handle: ServerHandle::new(),
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+            timeouts: Timeouts::default(),
+            limits: limits::ConnectionLimits::default(),
+            parser_limits: limits::ParserLimits::default(),
+            error_handler: None,
+            #[cfg(feature = "tls")]
+            tls: None
         }
     }
 
@@ -82,13 +192,44 @@ impl Server {
         self.address(addr)
     }
 
-    pub fn addr(mut self, addr: &str) -> Self {
-        self.address = Some(addr.parse().expect("Invalid address"));
+    pub fn addr(self, addr: &str) -> Self {
+        self.address(addr.parse().expect("Invalid address"))
+    }
+
+    /// Listens on the given address, alongside any other listener added to the builder.
+    pub fn address(self, address: SocketAddr) -> Self {
+        self.listener(ListenerConfig::tcp(address))
+    }
+
+    /// Listens on a Unix socket at the given path.
+    #[cfg(unix)]
+    pub fn unix_socket<P: Into<std::path::PathBuf>>(self, path: P) -> Self {
+        self.listener(ListenerConfig::unix(path))
+    }
+
+    /// The permission bits of the Unix socket file, such as `0o660` to let a proxy running
+    /// as another user in the same group connect. Applies to the last [Server::unix_socket].
+    #[cfg(unix)]
+    pub fn unix_socket_permissions(mut self, mode: u32) -> Self {
+        let last_unix_socket = self.listeners.iter_mut().rev().find_map(|listener| match &mut listener.address {
+            listener::ListenAddress::Unix(_, permissions) => Some(permissions),
+            _ => None
+        });
+        if let Some(permissions) = last_unix_socket {
+            *permissions = Some(mode);
+        }
+        self
+    }
+
+    /// Adds a listener, which can have a name and a TLS config of its own.
+    pub fn listener(mut self, config: ListenerConfig) -> Self {
+        self.listeners.push(config);
         self
     }
 
-    pub fn address(mut self, address: SocketAddr) -> Self {
-        self.address = Some(address);
+    /// Adds several listeners at once, such as the ones from [ListenerConfig::systemd].
+    pub fn listeners<I: IntoIterator<Item = ListenerConfig>>(mut self, configs: I) -> Self {
+        self.listeners.extend(configs);
         self
     }
 
@@ -97,20 +238,142 @@ impl Server {
         self
     }
 
+    /// How long connections are given to finish their requests once shutdown is triggered,
+    /// after which they're closed regardless. Defaults to 30 seconds.
+    pub fn shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.shutdown_timeout = timeout;
+        self
+    }
+
+    /// How long a connection may sit idle between requests before it's closed.
+    pub fn keep_alive_timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.keep_alive = timeout;
+        self
+    }
+
+    /// How long a client has to send a request head once it starts sending it, before it's
+    /// answered with `408 Request Timeout`.
+    pub fn request_head_timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.request_head = timeout;
+        self
+    }
+
+    /// How long reading a request body may go without receiving anything.
+    pub fn request_body_timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.request_body = timeout;
+        self
+    }
+
+    /// How long writing a response may go without the client reading any of it.
+    pub fn response_write_timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.response_write = timeout;
+        self
+    }
+
+    /// Caps the number of open connections, overall and per client IP. The counts can be
+    /// read through the server's [handle](Server::handle).
+    pub fn connection_limits(mut self, limits: limits::ConnectionLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Bounds the size of request lines, headers and bodies, past which requests are refused.
+    pub fn parser_limits(mut self, limits: limits::ParserLimits) -> Self {
+        self.parser_limits = limits;
+        self
+    }
+
+    /// Builds the responses to requests that are refused before reaching the router, such as
+    /// malformed ones, instead of the default plain-text ones. Over HTTP/2, malformed requests
+    /// are reset as RFC 9113 requires, so only those over the parser limits get a response.
+    pub fn error_handler<F>(mut self, handler: F) -> Self
+    where F: Fn(&response::error::RequestError) -> response::HttpResponse + Send + Sync + 'static {
+        self.error_handler = Some(Arc::new(handler));
+        self
+    }
+
+    /// A handle to shut the server down with once it's started.
+    pub fn handle(&self) -> ServerHandle {
+        self.handle.clone()
+    }
+
+    /// Serves over TLS with the PEM-encoded certificate chain and private key at the given
+    /// paths, which are reloaded whenever they change on disk.
+    #[cfg(feature = "tls")]
+    pub fn tls<C, K>(self, cert_chain: C, private_key: K) -> Self
+    where
+        C: Into<std::path::PathBuf>,
+        K: Into<std::path::PathBuf>
+    {
+        self.tls_config(tls::TlsConfig::from_pem_files(cert_chain, private_key))
+    }
+
+    #[cfg(feature = "tls")]
+    pub fn tls_config(mut self, config: tls::TlsConfig) -> Self {
+        self.tls = Some(config);
+        self
+    }
+
+    /// Shuts the server down gracefully once `signal` resolves, such as when Ctrl+C is pressed.
+    /// Returns once every connection is closed.
+    pub async fn start_with_shutdown<F>(self, signal: F) -> Result<()>
+    where F: Future<Output = ()> {
+        let handle = self.handle();
+        let shutdown = async move {
+            signal.await;
+            handle.shutdown();
+            std::future::pending().await
+        };
+        tokio::select! {
+            result = self.start() => result,
+            never = shutdown => never
+        }
+    }
+
+    /// Serves connections until the server is shut down through its [handle](Server::handle).
     pub async fn start(self) -> Result<()> {
+        let handle = self.handle();
+        let shutdown_timeout = self.shutdown_timeout;
+        let server = self.bind().await?;
+        server.listen_with_handle(&handle, shutdown_timeout).await
+    }
+
+    /// Binds every listener and serves connections on a new task, returning right away.
+    /// Binding to port 0 lets the OS pick a free port, which can be read back from the
+    /// returned server.
+    pub async fn spawn(self) -> Result<SpawnedServer> {
+        let handle = self.handle();
+        let shutdown_timeout = self.shutdown_timeout;
+        let server = self.bind().await?;
+        let listeners = server.listeners();
+        let task = {
+            let handle = handle.clone();
+            tokio::spawn(async move { server.listen_with_handle(&handle, shutdown_timeout).await })
+        };
+        Ok(SpawnedServer { handle, listeners, task })
+    }
+
+    async fn bind(self) -> Result<HttpServer> {
         let mut server = HttpServer::default();
         if let Some(router) = self.router {
             server.set_router(router)
         }
-        let addr = self.address.ok_or(ServerBuildError::UndefinedAddress)?;
-
-        server
-            .bind(&addr.to_string())
-            .await?;
-
-        server
-            .listen()
-            .await?;
-        Ok(())
+        server.set_timeouts(self.timeouts);
+        server.set_connection_limits(self.limits);
+        server.set_parser_limits(self.parser_limits);
+        if let Some(handler) = self.error_handler {
+            server.set_error_handler(handler);
+        }
+        #[cfg(feature = "tls")]
+        if let Some(config) = self.tls {
+            server.set_tls(config)?;
+        }
+        if self.listeners.is_empty() {
+            return Err(ServerBuildError::UndefinedAddress.into());
+        }
+        for listener in self.listeners {
+            server.add_listener(listener).await?;
+        }
+        Ok(server)
     }
 }
\ No newline at end of file
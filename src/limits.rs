@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Bounds how many connections the server handles at once. Unlimited by default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConnectionLimits {
+    /// How many connections may be open across all listeners
+    pub max_connections: Option<usize>,
+    /// How many connections may be open from a single client IP. Clients behind a PROXY
+    /// protocol header count as the address it names, and Unix sockets aren't limited.
+    pub max_per_ip: Option<usize>,
+    pub behavior: LimitBehavior
+}
+
+/// What happens to connections over a limit.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub enum LimitBehavior {
+    /// Stops accepting until a connection closes, leaving new ones waiting in the listen
+    /// backlog. Since the client isn't known before accepting, connections over the per-IP
+    /// limit are closed right away instead.
+    #[default]
+    Pause,
+    /// Accepts every connection, answering the ones over a limit with `503 Service
+    /// Unavailable` before closing them. HTTP/2 connections are closed without a response,
+    /// and so are TLS ones turned away before their handshake.
+    Reject
+}
+
+/// The connections a server has handled, for metrics.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct ConnectionStats {
+    /// Connections open right now
+    pub active: usize,
+    /// Connections that were let through since the server started
+    pub accepted: u64,
+    /// Connections that were turned away for being over a limit
+    pub rejected: u64
+}
+
+/// Bounds the size of the requests the server reads, which are refused before anything is
+/// allocated for them once they go over.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct ParserLimits {
+    /// How long the request line may be, line ending included. Answered with `414 URI Too
+    /// Long`. Defaults to 8 KiB.
+    pub max_request_line: usize,
+    /// How many bytes the header section may take up, line endings included. Answered with
+    /// `431 Request Header Fields Too Large`. Over HTTP/2, it's the decoded header list
+    /// that's bounded, counting 32 extra bytes per field, and it's advertised to clients as
+    /// SETTINGS_MAX_HEADER_LIST_SIZE. Defaults to 64 KiB.
+    pub max_header_bytes: usize,
+    /// How many header fields a request may have, which over HTTP/2 includes pseudo-header
+    /// fields such as `:path`. Answered with `431 Request Header Fields Too Large`. Defaults
+    /// to 100.
+    pub max_headers: usize,
+    /// How large a request body may be, which also applies to HTTP/2. Bodies that announce
+    /// a larger `Content-Length` are answered with `413 Payload Too Large` right away, and
+    /// the others fail to be read once they go over. Defaults to 16 MiB.
+    pub max_body_size: usize
+}
+
+impl Default for ParserLimits {
+    fn default() -> Self {
+        ParserLimits {
+            max_request_line: 8 * 1024,
+            max_header_bytes: 64 * 1024,
+            max_headers: 100,
+            max_body_size: 16 * 1024 * 1024
+        }
+    }
+}
+
+/// Counts the connections of a server, shared by its handle and all of its listeners.
+#[derive(Default)]
+pub(crate) struct ConnectionTracker {
+    active: AtomicUsize,
+    accepted: AtomicU64,
+    rejected: AtomicU64,
+    per_ip: Mutex<HashMap<IpAddr, usize>>
+}
+
+impl ConnectionTracker {
+    pub(crate) fn stats(&self) -> ConnectionStats {
+        ConnectionStats {
+            active: self.active.load(Ordering::Relaxed),
+            accepted: self.accepted.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed)
+        }
+    }
+
+    pub(crate) fn active_from(&self, ip: IpAddr) -> usize {
+        self.per_ip.lock().unwrap().get(&ip).copied().unwrap_or(0)
+    }
+}
+
+/// Applies a server's limits to the connections of all of its listeners.
+pub(crate) struct ConnectionLimiter {
+    limits: ConnectionLimits,
+    slots: Option<Arc<Semaphore>>,
+    tracker: Arc<ConnectionTracker>
+}
+
+impl ConnectionLimiter {
+    pub(crate) fn new(limits: ConnectionLimits, tracker: Arc<ConnectionTracker>) -> Self {
+        ConnectionLimiter {
+            limits,
+            slots: limits.max_connections.map(|max| Arc::new(Semaphore::new(max))),
+            tracker
+        }
+    }
+
+    pub(crate) fn behavior(&self) -> LimitBehavior {
+        self.limits.behavior
+    }
+
+    /// Waits for a free slot before the next connection is accepted, when pausing at the
+    /// limit. Resolves right away otherwise, leaving it to [ConnectionLimiter::admit].
+    pub(crate) async fn reserve(&self) -> Option<OwnedSemaphorePermit> {
+        match (&self.slots, self.limits.behavior) {
+            (Some(slots), LimitBehavior::Pause) => slots.clone().acquire_owned().await.ok(),
+            _ => None
+        }
+    }
+
+    /// Lets a connection in if it's within the limits, counting it until the returned
+    /// permit is dropped.
+    pub(crate) fn admit(&self, ip: Option<IpAddr>, reserved: Option<OwnedSemaphorePermit>) -> Option<ConnectionPermit> {
+        let slot = match (&self.slots, reserved) {
+            (_, Some(reserved)) => Some(reserved),
+            (Some(slots), None) => match slots.clone().try_acquire_owned() {
+                Ok(slot) => Some(slot),
+                Err(_) => return self.reject()
+            },
+            (None, None) => None
+        };
+        if let Some(ip) = ip {
+            let mut per_ip = self.tracker.per_ip.lock().unwrap();
+            let count = per_ip.entry(ip).or_default();
+            if self.limits.max_per_ip.is_some_and(|max| *count >= max) {
+                drop(per_ip);
+                return self.reject();
+            }
+            *count += 1;
+        }
+        self.tracker.active.fetch_add(1, Ordering::Relaxed);
+        self.tracker.accepted.fetch_add(1, Ordering::Relaxed);
+        Some(ConnectionPermit {
+            tracker: self.tracker.clone(),
+            ip,
+            _slot: slot
+        })
+    }
+
+    fn reject(&self) -> Option<ConnectionPermit> {
+        self.tracker.rejected.fetch_add(1, Ordering::Relaxed);
+        None
+    }
+}
+
+/// Held for as long as a connection is open.
+pub(crate) struct ConnectionPermit {
+    tracker: Arc<ConnectionTracker>,
+    ip: Option<IpAddr>,
+    _slot: Option<OwnedSemaphorePermit>
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        self.tracker.active.fetch_sub(1, Ordering::Relaxed);
+        if let Some(ip) = self.ip {
+            let mut per_ip = self.tracker.per_ip.lock().unwrap();
+            if let Some(count) = per_ip.get_mut(&ip) {
+                *count -= 1;
+                if *count == 0 {
+                    per_ip.remove(&ip);
+                }
+            }
+        }
+    }
+}
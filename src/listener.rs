@@ -0,0 +1,176 @@
+use crate::extractor::{ExtractorError, FromRequest};
+use crate::request::HttpRequest;
+use async_trait::async_trait;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use thiserror::Error;
+
+/// One of the addresses a server accepts connections on. Listeners without a name are
+/// named after the address they end up bound to.
+pub struct ListenerConfig {
+    pub(crate) address: ListenAddress,
+    pub(crate) name: Option<String>,
+    pub(crate) proxy_protocol: bool,
+    #[cfg(feature = "tls")]
+    pub(crate) tls: Option<crate::tls::TlsConfig>
+}
+
+pub(crate) enum ListenAddress {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(std::path::PathBuf, Option<u32>),
+    /// A socket that is already listening, which is adopted instead of bound
+    #[cfg(unix)]
+    Fd(std::os::fd::OwnedFd)
+}
+
+impl ListenerConfig {
+    pub fn tcp(address: SocketAddr) -> Self {
+        ListenerConfig::new(ListenAddress::Tcp(address))
+    }
+
+    /// A Unix socket at the given path. A socket file left behind by a server that is no
+    /// longer running is replaced.
+    #[cfg(unix)]
+    pub fn unix<P: Into<std::path::PathBuf>>(path: P) -> Self {
+        ListenerConfig::new(ListenAddress::Unix(path.into(), None))
+    }
+
+    /// A TCP or Unix socket that is already listening, such as one handed down by the
+    /// previous process during a restart.
+    #[cfg(unix)]
+    pub fn from_fd(fd: std::os::fd::OwnedFd) -> Self {
+        ListenerConfig::new(ListenAddress::Fd(fd))
+    }
+
+    /// The sockets passed through systemd socket activation, named after `LISTEN_FDNAMES` if
+    /// set. Empty unless `LISTEN_PID` is this process, which also keeps child processes that
+    /// inherit the variables from mistaking the sockets for theirs. The environment is left
+    /// untouched and only read once, so the sockets can only be claimed once per process.
+    #[cfg(unix)]
+    pub fn systemd() -> Result<Vec<ListenerConfig>, SystemdError> {
+        use std::os::fd::{FromRawFd, OwnedFd};
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::OnceLock;
+        const SD_LISTEN_FDS_START: i32 = 3;
+        static ACTIVATION: OnceLock<Option<(i32, Option<String>)>> = OnceLock::new();
+        static CLAIMED: AtomicBool = AtomicBool::new(false);
+
+        let activation = ACTIVATION.get_or_init(|| {
+            let pid = std::env::var("LISTEN_PID").ok().and_then(|pid| pid.parse::<u32>().ok());
+            let count = std::env::var("LISTEN_FDS").ok().and_then(|count| count.parse::<i32>().ok());
+            match count {
+                Some(count) if pid == Some(std::process::id()) => Some((count, std::env::var("LISTEN_FDNAMES").ok())),
+                _ => None
+            }
+        });
+        let Some((count, names)) = activation else {
+            return Ok(Vec::new());
+        };
+        if CLAIMED.swap(true, Ordering::AcqRel) {
+            return Err(SystemdError::AlreadyClaimed);
+        }
+
+        let mut names = names.iter().flat_map(|names| names.split(':'));
+        Ok((0..*count).map(|i| {
+            // SAFETY: LISTEN_PID hands these descriptors over to this process, and they can
+            // only be claimed once
+            let fd = unsafe { OwnedFd::from_raw_fd(SD_LISTEN_FDS_START + i) };
+            let config = ListenerConfig::from_fd(fd);
+            match names.next() {
+                Some(name) if !name.is_empty() => config.name(name),
+                _ => config
+            }
+        }).collect())
+    }
+
+    fn new(address: ListenAddress) -> Self {
+        ListenerConfig {
+            address,
+            name: None,
+            proxy_protocol: false,
+            #[cfg(feature = "tls")]
+            tls: None
+        }
+    }
+
+    /// The name handlers see through [ListenerInfo], such as `admin`.
+    pub fn name<N: Into<String>>(mut self, name: N) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// The permission bits of the socket file, such as `0o660`. Only applies to Unix sockets.
+    #[cfg(unix)]
+    pub fn permissions(mut self, mode: u32) -> Self {
+        if let ListenAddress::Unix(_, permissions) = &mut self.address {
+            *permissions = Some(mode);
+        }
+        self
+    }
+
+    /// Expects every connection to start with a PROXY protocol v1 or v2 header, as sent by
+    /// HAProxy or an AWS Network Load Balancer, and reports the client it names as the peer.
+    /// Connections without a valid header are closed, so only enable this behind such a proxy.
+    pub fn proxy_protocol(mut self) -> Self {
+        self.proxy_protocol = true;
+        self
+    }
+
+    /// Terminates TLS on this listener, instead of using the server's TLS config if any.
+    #[cfg(feature = "tls")]
+    pub fn tls(mut self, config: crate::tls::TlsConfig) -> Self {
+        self.tls = Some(config);
+        self
+    }
+}
+
+#[cfg(unix)]
+#[derive(Debug, Error)]
+pub enum SystemdError {
+    #[error("The sockets passed by systemd were already claimed")]
+    AlreadyClaimed
+}
+
+/// The listener that accepted the connection a request came through. Not available for
+/// connections handed over through [serve_connection](crate::serve_connection).
+#[derive(Debug, Clone)]
+pub struct ListenerInfo {
+    name: Arc<str>,
+    local_addr: Option<SocketAddr>
+}
+
+impl ListenerInfo {
+    pub(crate) fn new(name: Arc<str>, local_addr: Option<SocketAddr>) -> Self {
+        ListenerInfo { name, local_addr }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The address the listener is bound to, unless it's a Unix socket.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr
+    }
+}
+
+#[async_trait]
+impl FromRequest for ListenerInfo {
+    async fn from_request(_index: usize, request: &mut HttpRequest) -> Result<Self, ExtractorError>
+    where
+        Self: Sized
+    {
+        request.connection.listener.clone().ok_or(ExtractorError::MissingListener)
+    }
+}
+
+#[async_trait]
+impl FromRequest for Option<ListenerInfo> {
+    async fn from_request(_index: usize, request: &mut HttpRequest) -> Result<Self, ExtractorError>
+    where
+        Self: Sized
+    {
+        Ok(request.connection.listener.clone())
+    }
+}
@@ -1,21 +1,28 @@
-use crate::parser::body::LazyBodyReader;
+use crate::limits::ParserLimits;
+use crate::parser::body::{BodyFraming, LazyBodyReader, ReleasedReader};
 use crate::parser::HttpParserError;
-use crate::request::{HttpHeader, HttpHeaderMap, HttpMethod, HttpProtocol, HttpRequest, HttpScheme};
+use crate::protocol::stream::ConnectionReader;
+use crate::protocol::ConnectionInfo;
+use crate::request::{HttpHeader, HeaderMap, HttpMethod, HttpProtocol, HttpRequest, HttpVersion};
 use std::collections::HashMap;
-use std::net::SocketAddr;
-use tokio::io::{AsyncBufReadExt, BufReader};
-use tokio::net::tcp::OwnedReadHalf;
-use tokio::sync::OnceCell;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt};
+use tokio::sync::{oneshot, OnceCell};
 
-const INITIAL_BUFFER_SIZE: usize = 4096;
-const MAX_HEADER_SIZE: usize = 8192;
+pub(crate) const INITIAL_BUFFER_SIZE: usize = 4096;
 
 pub struct AlphaHttpParser {
-    method_map: HashMap<&'static str, HttpMethod>
+    method_map: HashMap<&'static str, HttpMethod>,
+    limits: ParserLimits
 }
 
 impl AlphaHttpParser {
     pub fn new() -> Self {
+        Self::with_limits(ParserLimits::default())
+    }
+
+    pub fn with_limits(limits: ParserLimits) -> Self {
         let mut method_map = HashMap::with_capacity(8);
         method_map.insert("GET", HttpMethod::GET);
         method_map.insert("POST", HttpMethod::POST);
@@ -26,15 +33,19 @@ impl AlphaHttpParser {
         method_map.insert("PATCH", HttpMethod::PATCH);
         method_map.insert("TRACE", HttpMethod::TRACE);
 
-        AlphaHttpParser { method_map }
+        AlphaHttpParser { method_map, limits }
     }
 
-    async fn parse_request_line(&self, reader: &mut BufReader<OwnedReadHalf>) -> Result<(HttpMethod, String), HttpParserError> {
-        let mut line = Vec::with_capacity(INITIAL_BUFFER_SIZE);
-        let bytes_read = reader.read_until(b'\n', &mut line).await.map_err(|_| HttpParserError::RequestLine)?;
+    pub fn limits(&self) -> ParserLimits {
+        self.limits
+    }
 
-        if bytes_read < 5 {
-            return Err(HttpParserError::InvalidRequestLine);
+    async fn parse_request_line(&self, reader: &mut ConnectionReader) -> Result<(HttpMethod, String, HttpVersion), HttpParserError> {
+        let mut line = Vec::with_capacity(INITIAL_BUFFER_SIZE.min(self.limits.max_request_line));
+        match read_line(reader, &mut line, self.limits.max_request_line).await? {
+            LineEnd::Complete => {},
+            LineEnd::TooLong => return Err(HttpParserError::UriTooLong),
+            LineEnd::Closed => return Err(HttpParserError::RequestLine)
         }
 
         let line_str = match std::str::from_utf8(&line) {
@@ -45,63 +56,215 @@ impl AlphaHttpParser {
         let mut parts = line_str.split_whitespace();
         let method_str = parts.next().ok_or(HttpParserError::InvalidRequestLine)?.trim_end();
         let path = parts.next().ok_or(HttpParserError::InvalidRequestLine)?.trim_end().to_string();
+        let version = match parts.next().ok_or(HttpParserError::InvalidRequestLine)? {
+            "HTTP/1.1" => HttpVersion::Http11,
+            "HTTP/1.0" => HttpVersion::Http10,
+            version if is_http_version(version) => return Err(HttpParserError::InvalidVersion),
+            _ => return Err(HttpParserError::InvalidRequestLine)
+        };
+        if parts.next().is_some() || !is_token(method_str) {
+            return Err(HttpParserError::InvalidRequestLine);
+        }
 
         let method = self.method_map.get(method_str).ok_or(HttpParserError::InvalidMethod)?;
 
-        Ok((*method, path))
+        Ok((*method, path, version))
     }
 
-    async fn parse_headers(&self, reader: &mut BufReader<OwnedReadHalf>) -> Result<HttpHeaderMap, HttpParserError> {
-        let mut headers = HttpHeaderMap::with_capacity(16);
-        let mut buffer = Vec::with_capacity(INITIAL_BUFFER_SIZE);
-
-        loop {
-            buffer.clear();
-            let bytes_read = reader.read_until(b'\n', &mut buffer).await.map_err(|_| HttpParserError::HeaderLine)?;
-
-            if bytes_read == 0 || buffer.len() <= 2 {
-                break;
-            }
-
-            if buffer.len() > MAX_HEADER_SIZE {
-                return Err(HttpParserError::HeaderLine);
-            }
-
-            if let Some(idx) = buffer.iter().position(|&b| b == b':') {
-                let key = std::str::from_utf8(&buffer[..idx]).map_err(|_| HttpParserError::HeaderLine)?;
-                let value = std::str::from_utf8(&buffer[idx + 1..]).map_err(|_| HttpParserError::HeaderLine)?;
+    async fn parse_headers(&self, reader: &mut ConnectionReader) -> Result<HeaderMap, HttpParserError> {
+        read_header_fields(reader, &self.limits).await
+    }
 
-                headers.insert(HttpHeader::from_name(&key.trim().to_lowercase()), value.trim().to_string());
+    async fn parse_head(
+        &self,
+        reader: &mut ConnectionReader
+    ) -> Result<(HttpMethod, String, HttpVersion, HeaderMap, BodyFraming), HttpParserError> {
+        let (method, path, version) = self.parse_request_line(reader).await?;
+        let headers = self.parse_headers(reader).await?;
+        let framing = body_framing(&headers)?;
+        if let BodyFraming::Length(length) = framing {
+            if length > self.limits.max_body_size {
+                return Err(HttpParserError::BodyTooLarge);
             }
         }
-
-        Ok(headers)
+        Ok((method, path, version, headers, framing))
     }
 
-    pub(crate) async fn parse(&self, addr: SocketAddr, stream: OwnedReadHalf) -> Result<HttpRequest, HttpParserError> {
-        let mut reader = BufReader::with_capacity(INITIAL_BUFFER_SIZE, stream);
-        let (method, path) = self.parse_request_line(&mut reader).await?;
-        let headers = self.parse_headers(&mut reader).await?;
-
-        let content_length = headers
-            .get(&HttpHeader::ContentLength)
-            .ok_or(HttpParserError::InvalidContentLength)?
-            .parse::<usize>()
-            .map_err(|_| HttpParserError::InvalidContentLength)?;
+    /// Parses the head of the next request, leaving the body to be read lazily. On failure,
+    /// the reader is handed back so that the client can still be answered.
+    pub(crate) async fn parse(
+        &self,
+        connection: &Arc<ConnectionInfo>,
+        mut reader: ConnectionReader,
+        body_timeout: Duration
+    ) -> Result<(HttpRequest, oneshot::Receiver<ReleasedReader>), (HttpParserError, ConnectionReader)> {
+        let (method, path, version, headers, framing) = match self.parse_head(&mut reader).await {
+            Ok(head) => head,
+            Err(error) => return Err((error, reader))
+        };
+        let content_length = match framing {
+            BodyFraming::Empty => Some(0),
+            BodyFraming::Length(length) => Some(length),
+            BodyFraming::Chunked => None
+        };
 
-        let body_reader = LazyBodyReader::new(reader, content_length);
+        let keep_alive = wants_keep_alive(version, &headers);
+        let (body_reader, released) = LazyBodyReader::new(reader, framing, body_timeout, self.limits);
 
-        Ok(HttpRequest {
+        let request = HttpRequest {
             protocol: HttpProtocol::HTTP1,
             path,
-            scheme: HttpScheme::HTTP,
+            scheme: connection.scheme,
             method,
             headers,
             body_reader,
             content_length,
-            peer_addr: addr,
+            peer_addr: connection.peer_addr,
             flow: OnceCell::new(),
             path_values: OnceCell::new(),
-        })
+            version,
+            keep_alive,
+            connection: connection.clone()
+        };
+        Ok((request, released))
+    }
+}
+
+/// Reads header fields up to the empty line that ends the header section. Also used
+/// for the trailer section of chunked bodies, which shares the same syntax.
+pub(crate) async fn read_header_fields(reader: &mut ConnectionReader, limits: &ParserLimits) -> Result<HeaderMap, HttpParserError> {
+    let mut headers = HeaderMap::with_capacity(16.min(limits.max_headers));
+    let mut buffer = Vec::with_capacity(INITIAL_BUFFER_SIZE.min(limits.max_header_bytes));
+    let mut section_size = 0;
+    let mut count = 0;
+
+    loop {
+        buffer.clear();
+        // Each line may only take up what's left of the section, so nothing past it is read
+        let remaining = limits.max_header_bytes - section_size;
+        match read_line(reader, &mut buffer, remaining).await? {
+            LineEnd::Complete => {},
+            LineEnd::TooLong => return Err(HttpParserError::HeadersTooLarge),
+            LineEnd::Closed => return Err(HttpParserError::HeaderLine)
+        }
+        section_size += buffer.len();
+
+        if buffer == b"\r\n" || buffer == b"\n" {
+            break;
+        }
+        count += 1;
+        if count > limits.max_headers {
+            return Err(HttpParserError::HeadersTooLarge);
+        }
+
+        // Lines folded onto the previous field, or without a colon, are malformed
+        let idx = buffer.iter().position(|&b| b == b':').ok_or(HttpParserError::InvalidHeader)?;
+        let key = std::str::from_utf8(&buffer[..idx]).map_err(|_| HttpParserError::InvalidHeader)?;
+        let value = std::str::from_utf8(&buffer[idx + 1..]).map_err(|_| HttpParserError::InvalidHeader)?;
+        // Whitespace before the colon has been used to smuggle requests past intermediaries
+        if !is_token(key) {
+            return Err(HttpParserError::InvalidHeader);
+        }
+
+        headers
+            .try_append(HttpHeader::from_name(&key.to_lowercase()), value.trim())
+            .map_err(|_| HttpParserError::InvalidHeader)?;
+    }
+
+    Ok(headers)
+}
+
+enum LineEnd {
+    Complete,
+    TooLong,
+    Closed
+}
+
+/// Reads a line into `buffer`, line ending included, without reading more than `limit` bytes.
+async fn read_line(reader: &mut ConnectionReader, buffer: &mut Vec<u8>, limit: usize) -> Result<LineEnd, HttpParserError> {
+    let read = reader
+        .take(limit as u64)
+        .read_until(b'\n', buffer)
+        .await
+        .map_err(|_| HttpParserError::RequestLine)?;
+    Ok(match buffer.last() {
+        Some(b'\n') => LineEnd::Complete,
+        _ if read == limit => LineEnd::TooLong,
+        _ => LineEnd::Closed
+    })
+}
+
+/// Whether the value is a token as defined by RFC 9110 section 5.6.2, which methods and
+/// field names must be.
+pub(crate) fn is_token(value: &str) -> bool {
+    !value.is_empty() && value.bytes().all(|byte| byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte))
+}
+
+/// Whether the value looks like an HTTP version, such as `HTTP/2.0`, even if not one we speak.
+fn is_http_version(value: &str) -> bool {
+    value
+        .strip_prefix("HTTP/")
+        .is_some_and(|number| !number.is_empty() && number.bytes().all(|byte| byte.is_ascii_digit() || byte == b'.'))
+}
+
+/// Determines how the request body is delimited, following RFC 9112 section 6.3.
+/// Unlike responses, a request without `Content-Length` or `Transfer-Encoding` never
+/// has a body, whatever its method is.
+fn body_framing(headers: &HeaderMap) -> Result<BodyFraming, HttpParserError> {
+    if headers.contains_key(&HttpHeader::TransferEncoding) {
+        // Letting one of the two win would allow request smuggling through intermediaries
+        // that picked the other one, so such requests are refused altogether.
+        if headers.contains_key(&HttpHeader::ContentLength) {
+            return Err(HttpParserError::AmbiguousBodyLength);
+        }
+        let mut codings = list_values(headers, &HttpHeader::TransferEncoding).filter(|coding| !coding.is_empty());
+        return match (codings.next(), codings.next()) {
+            (Some(coding), None) if coding.eq_ignore_ascii_case("chunked") => Ok(BodyFraming::Chunked),
+            _ => Err(HttpParserError::UnsupportedTransferEncoding)
+        };
+    }
+
+    match content_length(headers)? {
+        Some(length) => Ok(BodyFraming::Length(length)),
+        None => Ok(BodyFraming::Empty)
+    }
+}
+
+/// The length announced by `Content-Length`, if there's one. Identical lengths, listed
+/// (e.g. "42, 42") or in repeated fields, mean the same as a single one.
+pub(crate) fn content_length(headers: &HeaderMap) -> Result<Option<usize>, HttpParserError> {
+    if !headers.contains_key(&HttpHeader::ContentLength) {
+        return Ok(None);
+    }
+    let mut lengths = list_values(headers, &HttpHeader::ContentLength).map(parse_content_length);
+    let length = lengths.next()
+        .flatten()
+        .ok_or(HttpParserError::InvalidContentLength)?;
+    if lengths.any(|other| other != Some(length)) {
+        return Err(HttpParserError::InvalidContentLength);
+    }
+    Ok(Some(length))
+}
+
+/// The elements of a comma-separated list field, across every field with that name.
+fn list_values<'a>(headers: &'a HeaderMap, header: &'a HttpHeader) -> impl Iterator<Item = &'a str> + 'a {
+    headers.get_all(header).flat_map(|value| value.split(',')).map(str::trim)
+}
+
+fn parse_content_length(value: &str) -> Option<usize> {
+    if value.is_empty() || !value.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    value.parse().ok()
+}
+
+/// HTTP/1.1 connections persist unless the client asks to close them, while HTTP/1.0
+/// ones only do so when the client explicitly opts in with `Connection: keep-alive`.
+fn wants_keep_alive(version: HttpVersion, headers: &HeaderMap) -> bool {
+    let has_option = |option: &str| list_values(headers, &HttpHeader::Connection).any(|token| token.eq_ignore_ascii_case(option));
+
+    match version {
+        HttpVersion::Http11 | HttpVersion::Http2 => !has_option("close"),
+        HttpVersion::Http10 => has_option("keep-alive")
     }
 }
\ No newline at end of file
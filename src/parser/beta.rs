@@ -0,0 +1,348 @@
+use crate::parser::HttpParserError;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+pub(crate) const CONNECTION_PREFACE: &[u8; 24] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+pub(crate) const DEFAULT_MAX_FRAME_SIZE: usize = 16384;
+const FRAME_HEADER_SIZE: usize = 9;
+
+pub(crate) const FLAG_END_STREAM: u8 = 0x1;
+pub(crate) const FLAG_ACK: u8 = 0x1;
+pub(crate) const FLAG_END_HEADERS: u8 = 0x4;
+const FLAG_PADDED: u8 = 0x8;
+const FLAG_PRIORITY: u8 = 0x20;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum FrameKind {
+    Data = 0x0,
+    Headers = 0x1,
+    Priority = 0x2,
+    RstStream = 0x3,
+    Settings = 0x4,
+    PushPromise = 0x5,
+    Ping = 0x6,
+    GoAway = 0x7,
+    WindowUpdate = 0x8,
+    Continuation = 0x9
+}
+
+impl FrameKind {
+    fn from_code(code: u8) -> Option<FrameKind> {
+        match code {
+            0x0 => Some(FrameKind::Data),
+            0x1 => Some(FrameKind::Headers),
+            0x2 => Some(FrameKind::Priority),
+            0x3 => Some(FrameKind::RstStream),
+            0x4 => Some(FrameKind::Settings),
+            0x5 => Some(FrameKind::PushPromise),
+            0x6 => Some(FrameKind::Ping),
+            0x7 => Some(FrameKind::GoAway),
+            0x8 => Some(FrameKind::WindowUpdate),
+            0x9 => Some(FrameKind::Continuation),
+            _ => None
+        }
+    }
+}
+
+/// Error codes carried by RST_STREAM and GOAWAY frames (RFC 9113 section 7).
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum ErrorCode {
+    NoError = 0x0,
+    ProtocolError = 0x1,
+    InternalError = 0x2,
+    FlowControlError = 0x3,
+    SettingsTimeout = 0x4,
+    StreamClosed = 0x5,
+    FrameSizeError = 0x6,
+    RefusedStream = 0x7,
+    Cancel = 0x8,
+    CompressionError = 0x9,
+    ConnectError = 0xa,
+    EnhanceYourCalm = 0xb,
+    InadequateSecurity = 0xc,
+    Http11Required = 0xd
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum Setting {
+    HeaderTableSize(u32),
+    EnablePush(u32),
+    MaxConcurrentStreams(u32),
+    InitialWindowSize(u32),
+    MaxFrameSize(u32),
+    MaxHeaderListSize(u32)
+}
+
+impl Setting {
+    fn from_pair(identifier: u16, value: u32) -> Option<Setting> {
+        match identifier {
+            0x1 => Some(Setting::HeaderTableSize(value)),
+            0x2 => Some(Setting::EnablePush(value)),
+            0x3 => Some(Setting::MaxConcurrentStreams(value)),
+            0x4 => Some(Setting::InitialWindowSize(value)),
+            0x5 => Some(Setting::MaxFrameSize(value)),
+            0x6 => Some(Setting::MaxHeaderListSize(value)),
+            _ => None
+        }
+    }
+
+    pub(crate) fn to_pair(self) -> (u16, u32) {
+        match self {
+            Setting::HeaderTableSize(value) => (0x1, value),
+            Setting::EnablePush(value) => (0x2, value),
+            Setting::MaxConcurrentStreams(value) => (0x3, value),
+            Setting::InitialWindowSize(value) => (0x4, value),
+            Setting::MaxFrameSize(value) => (0x5, value),
+            Setting::MaxHeaderListSize(value) => (0x6, value)
+        }
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub(crate) enum Frame {
+    Data {
+        stream_id: u32,
+        data: Vec<u8>,
+        end_stream: bool,
+        /// The whole payload length, padding included, which is what flow control counts
+        flow_controlled: usize
+    },
+    Headers {
+        stream_id: u32,
+        block: Vec<u8>,
+        end_stream: bool,
+        end_headers: bool
+    },
+    Priority {
+        stream_id: u32
+    },
+    RstStream {
+        stream_id: u32,
+        error_code: u32
+    },
+    Settings {
+        ack: bool,
+        settings: Vec<Setting>
+    },
+    PushPromise {
+        stream_id: u32
+    },
+    Ping {
+        ack: bool,
+        payload: [u8; 8]
+    },
+    GoAway {
+        last_stream_id: u32,
+        error_code: u32
+    },
+    WindowUpdate {
+        stream_id: u32,
+        increment: u32
+    },
+    Continuation {
+        stream_id: u32,
+        block: Vec<u8>,
+        end_headers: bool
+    },
+    /// Frames of unknown types must be ignored
+    Unknown
+}
+
+pub struct BetaHttpParser {
+    max_frame_size: usize
+}
+
+impl BetaHttpParser {
+    pub fn new() -> Self {
+        BetaHttpParser {
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE
+        }
+    }
+
+    pub(crate) async fn parse_preface<R>(&self, reader: &mut R) -> Result<(), HttpParserError>
+    where R: AsyncRead + Unpin {
+        let mut preface = [0; CONNECTION_PREFACE.len()];
+        reader.read_exact(&mut preface).await.map_err(|_| HttpParserError::ConnectionPreface)?;
+        if &preface != CONNECTION_PREFACE {
+            return Err(HttpParserError::ConnectionPreface);
+        }
+        Ok(())
+    }
+
+    /// Reads the next frame, or `None` if the connection was closed between frames.
+    pub(crate) async fn parse_frame<R>(&self, reader: &mut R) -> Result<Option<Frame>, HttpParserError>
+    where R: AsyncRead + Unpin {
+        let mut header = [0; FRAME_HEADER_SIZE];
+        match reader.read_exact(&mut header).await {
+            Ok(_) => {},
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(_) => return Err(HttpParserError::FrameHeader)
+        }
+
+        let length = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+        let kind = header[3];
+        let flags = header[4];
+        let stream_id = u32::from_be_bytes([header[5], header[6], header[7], header[8]]) & 0x7fff_ffff;
+        if length > self.max_frame_size {
+            return Err(HttpParserError::FrameSize);
+        }
+
+        let mut payload = vec![0; length];
+        reader.read_exact(&mut payload).await.map_err(|_| HttpParserError::FramePayload)?;
+
+        match FrameKind::from_code(kind) {
+            Some(kind) => parse_payload(kind, flags, stream_id, payload).map(Some),
+            None => Ok(Some(Frame::Unknown))
+        }
+    }
+}
+
+fn parse_payload(kind: FrameKind, flags: u8, stream_id: u32, payload: Vec<u8>) -> Result<Frame, HttpParserError> {
+    let requires_stream = !matches!(kind, FrameKind::Settings | FrameKind::Ping | FrameKind::GoAway | FrameKind::WindowUpdate);
+    if requires_stream && stream_id == 0 {
+        return Err(HttpParserError::InvalidStream);
+    }
+    if matches!(kind, FrameKind::Settings | FrameKind::Ping | FrameKind::GoAway) && stream_id != 0 {
+        return Err(HttpParserError::InvalidStream);
+    }
+
+    match kind {
+        FrameKind::Data => {
+            let flow_controlled = payload.len();
+            let data = strip_padding(flags, payload)?;
+            Ok(Frame::Data {
+                stream_id,
+                data,
+                end_stream: flags & FLAG_END_STREAM != 0,
+                flow_controlled
+            })
+        },
+        FrameKind::Headers => {
+            let mut block = strip_padding(flags, payload)?;
+            if flags & FLAG_PRIORITY != 0 {
+                if block.len() < 5 {
+                    return Err(HttpParserError::FrameSize);
+                }
+                block.drain(..5);
+            }
+            Ok(Frame::Headers {
+                stream_id,
+                block,
+                end_stream: flags & FLAG_END_STREAM != 0,
+                end_headers: flags & FLAG_END_HEADERS != 0
+            })
+        },
+        FrameKind::Priority => {
+            if payload.len() != 5 {
+                return Err(HttpParserError::FrameSize);
+            }
+            Ok(Frame::Priority { stream_id })
+        },
+        FrameKind::RstStream => {
+            let error_code = read_u32(&payload).ok_or(HttpParserError::FrameSize)?;
+            if payload.len() != 4 {
+                return Err(HttpParserError::FrameSize);
+            }
+            Ok(Frame::RstStream { stream_id, error_code })
+        },
+        FrameKind::Settings => {
+            let ack = flags & FLAG_ACK != 0;
+            if !payload.len().is_multiple_of(6) || (ack && !payload.is_empty()) {
+                return Err(HttpParserError::FrameSize);
+            }
+            Ok(Frame::Settings { ack, settings: parse_settings(&payload) })
+        },
+        FrameKind::PushPromise => Ok(Frame::PushPromise { stream_id }),
+        FrameKind::Ping => {
+            let payload: [u8; 8] = payload.try_into().map_err(|_| HttpParserError::FrameSize)?;
+            Ok(Frame::Ping { ack: flags & FLAG_ACK != 0, payload })
+        },
+        FrameKind::GoAway => {
+            if payload.len() < 8 {
+                return Err(HttpParserError::FrameSize);
+            }
+            Ok(Frame::GoAway {
+                last_stream_id: read_u32(&payload).unwrap() & 0x7fff_ffff,
+                error_code: read_u32(&payload[4..]).unwrap()
+            })
+        },
+        FrameKind::WindowUpdate => {
+            if payload.len() != 4 {
+                return Err(HttpParserError::FrameSize);
+            }
+            Ok(Frame::WindowUpdate {
+                stream_id,
+                increment: read_u32(&payload).unwrap() & 0x7fff_ffff
+            })
+        },
+        FrameKind::Continuation => Ok(Frame::Continuation {
+            stream_id,
+            block: payload,
+            end_headers: flags & FLAG_END_HEADERS != 0
+        })
+    }
+}
+
+fn parse_settings(payload: &[u8]) -> Vec<Setting> {
+    payload
+        .chunks_exact(6)
+        .filter_map(|pair| Setting::from_pair(
+            u16::from_be_bytes([pair[0], pair[1]]),
+            u32::from_be_bytes([pair[2], pair[3], pair[4], pair[5]])
+        ))
+        .collect()
+}
+
+/// Parses the `HTTP2-Settings` field of an h2c upgrade request, which holds the payload
+/// of a SETTINGS frame encoded as unpadded base64url.
+pub(crate) fn parse_http2_settings(value: &str) -> Option<Vec<Setting>> {
+    let payload = decode_base64url(value.trim())?;
+    if !payload.len().is_multiple_of(6) {
+        return None;
+    }
+    Some(parse_settings(&payload))
+}
+
+fn decode_base64url(encoded: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(encoded.len() * 3 / 4);
+    let mut buffer: u32 = 0;
+    let mut buffered_bits = 0;
+
+    // Padding isn't supposed to be there, but it's harmless to accept it
+    for byte in encoded.trim_end_matches('=').bytes() {
+        let value = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'-' => 62,
+            b'_' => 63,
+            _ => return None
+        };
+        buffer = (buffer << 6) | value as u32;
+        buffered_bits += 6;
+        if buffered_bits >= 8 {
+            buffered_bits -= 8;
+            decoded.push((buffer >> buffered_bits) as u8);
+        }
+    }
+    Some(decoded)
+}
+
+fn strip_padding(flags: u8, mut payload: Vec<u8>) -> Result<Vec<u8>, HttpParserError> {
+    if flags & FLAG_PADDED == 0 {
+        return Ok(payload);
+    }
+    let padding = *payload.first().ok_or(HttpParserError::FrameSize)? as usize;
+    if padding >= payload.len() {
+        return Err(HttpParserError::InvalidFrame);
+    }
+    payload.truncate(payload.len() - padding);
+    payload.remove(0);
+    Ok(payload)
+}
+
+fn read_u32(bytes: &[u8]) -> Option<u32> {
+    let bytes: [u8; 4] = bytes.get(..4)?.try_into().ok()?;
+    Some(u32::from_be_bytes(bytes))
+}
@@ -1,36 +1,365 @@
-use tokio::io::{AsyncReadExt, BufReader};
-use tokio::net::tcp::OwnedReadHalf;
+use crate::limits::ParserLimits;
+use crate::parser::alpha::read_header_fields;
+use crate::protocol::stream::ConnectionReader;
+use crate::request::HeaderMap;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt};
+use tokio::sync::{mpsc, oneshot};
+
+const MAX_CHUNK_SIZE_LINE: usize = 4096;
+const MAX_READ_SIZE: usize = 16384;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum BodyFraming {
+    Empty,
+    Length(usize),
+    Chunked
+}
+
+#[derive(Debug)]
+pub enum BodyError {
+    ConnectionClosed,
+    InvalidChunkSize,
+    InvalidChunkTerminator,
+    InvalidTrailer,
+    StreamReset,
+    /// The body is larger than the server accepts
+    TooLarge,
+    /// Nothing arrived for longer than the server's body timeout
+    TimedOut,
+    Io(std::io::Error)
+}
+
+impl From<std::io::Error> for BodyError {
+    fn from(error: std::io::Error) -> Self {
+        match error.kind() {
+            std::io::ErrorKind::UnexpectedEof => BodyError::ConnectionClosed,
+            _ => BodyError::Io(error)
+        }
+    }
+}
+
+/// Body data forwarded by a multiplexed connection to the stream it belongs to.
+pub(crate) enum StreamBodyEvent {
+    Data(Vec<u8>),
+    Trailers(HeaderMap),
+    End,
+    Reset
+}
+
+enum BodySource {
+    Connection(BodyDecoder),
+    Stream(StreamBody)
+}
+
+struct StreamBody {
+    events: mpsc::UnboundedReceiver<StreamBodyEvent>,
+    timeout: Duration,
+    /// How much more data the stream may receive before the body is too large
+    remaining: usize,
+    /// Tells the connection how many bytes were consumed, so it can grant more flow-control window
+    on_consumed: Box<dyn Fn(usize) + Send + Sync>
+}
 
 pub struct LazyBodyReader {
-    reader: BufReader<OwnedReadHalf>,
-    buffer: Box<[u8]>,
-    consumed: bool
+    source: Option<BodySource>,
+    buffer: Vec<u8>,
+    trailers: Option<HeaderMap>,
+    consumed: bool,
+    release: Option<oneshot::Sender<ReleasedReader>>
 }
 
 impl LazyBodyReader {
-    pub fn new(reader: BufReader<OwnedReadHalf>, content_length: usize) -> Self {
-        LazyBodyReader {
-            reader,
-            buffer: vec![0; content_length].into_boxed_slice(),
-            consumed: false
+    /// A body read straight from the connection, where each read fails if nothing arrives
+    /// within `timeout`. The trailers and the size of chunked bodies are held to `limits`.
+    pub(crate) fn new(
+        reader: ConnectionReader,
+        framing: BodyFraming,
+        timeout: Duration,
+        limits: ParserLimits
+    ) -> (Self, oneshot::Receiver<ReleasedReader>) {
+        let (release, released) = oneshot::channel();
+        let mut body_reader = LazyBodyReader {
+            source: Some(BodySource::Connection(BodyDecoder::new(reader, framing, timeout, limits))),
+            buffer: Vec::new(),
+            trailers: None,
+            consumed: false,
+            release: Some(release)
+        };
+        if framing == BodyFraming::Empty {
+            body_reader.finish();
         }
+        (body_reader, released)
     }
 
-    pub async fn consume_all(mut self) -> Box<[u8]> {
-        if self.consumed {
-            return self.buffer;
+    /// A body fed by a multiplexed connection, which pushes the events for this stream as
+    /// they arrive. Passing `None` means the stream was closed along with its headers.
+    pub(crate) fn from_stream<F>(
+        events: Option<mpsc::UnboundedReceiver<StreamBodyEvent>>,
+        timeout: Duration,
+        max_size: usize,
+        on_consumed: F
+    ) -> Self
+    where F: Fn(usize) + Send + Sync + 'static {
+        let mut body_reader = LazyBodyReader {
+            source: events.map(|events| BodySource::Stream(StreamBody {
+                events,
+                timeout,
+                remaining: max_size,
+                on_consumed: Box::new(on_consumed)
+            })),
+            buffer: Vec::new(),
+            trailers: None,
+            consumed: false,
+            release: None
+        };
+        if body_reader.source.is_none() {
+            body_reader.finish();
         }
-        self.reader.read_exact(&mut self.buffer).await.unwrap();
-        self.consumed = true;
-        self.buffer
+        body_reader
+    }
+
+    pub async fn consume_all(mut self) -> Result<Box<[u8]>, BodyError> {
+        self.read_all().await?;
+        Ok(std::mem::take(&mut self.buffer).into_boxed_slice())
     }
 
-    pub async fn read_all(&mut self) -> &[u8] {
-        if self.consumed {
-            return &self.buffer;
+    /// Buffers the remainder of the body and returns everything buffered so far. Data
+    /// already handed out by [LazyBodyReader::next_chunk] isn't part of it.
+    pub async fn read_all(&mut self) -> Result<&[u8], BodyError> {
+        while !self.consumed {
+            if let Some(chunk) = self.read_chunk().await? {
+                self.buffer.extend_from_slice(&chunk);
+            }
         }
-        self.reader.read_exact(&mut self.buffer).await.unwrap();
+        Ok(&self.buffer)
+    }
+
+    /// Reads the next piece of the body as it arrives from the client, returning `None`
+    /// once the body is over. For chunked bodies, each piece belongs to a single chunk.
+    pub async fn next_chunk(&mut self) -> Result<Option<Vec<u8>>, BodyError> {
+        while !self.consumed {
+            match self.read_chunk().await? {
+                Some(chunk) if !chunk.is_empty() => return Ok(Some(chunk)),
+                _ => continue
+            }
+        }
+        Ok(None)
+    }
+
+    /// Reads the rest of the body so that the connection is released, while the body can
+    /// still be read as usual from the copy kept in memory.
+    pub(crate) async fn detach(&mut self) -> Result<(), BodyError> {
+        self.read_all().await?;
+        let (sender, events) = mpsc::unbounded_channel();
+        let _ = sender.send(StreamBodyEvent::Data(std::mem::take(&mut self.buffer)));
+        if let Some(trailers) = self.trailers.take() {
+            let _ = sender.send(StreamBodyEvent::Trailers(trailers));
+        }
+        let _ = sender.send(StreamBodyEvent::End);
+        *self = LazyBodyReader::from_stream(Some(events), Duration::MAX, usize::MAX, |_| {});
+        Ok(())
+    }
+
+    /// The trailer fields sent after a chunked body. Only available once the body
+    /// has been read to the end.
+    pub fn trailers(&self) -> Option<&HeaderMap> {
+        self.trailers.as_ref()
+    }
+
+    async fn read_chunk(&mut self) -> Result<Option<Vec<u8>>, BodyError> {
+        let chunk = match self.source.as_mut().expect("Body reader already released") {
+            BodySource::Connection(decoder) => decoder.next_chunk().await?,
+            BodySource::Stream(stream) => match tokio::time::timeout(stream.timeout, stream.events.recv()).await.map_err(|_| BodyError::TimedOut)? {
+                Some(StreamBodyEvent::Data(data)) => {
+                    (stream.on_consumed)(data.len());
+                    stream.remaining = stream.remaining.checked_sub(data.len()).ok_or(BodyError::TooLarge)?;
+                    Some(data)
+                },
+                Some(StreamBodyEvent::Trailers(trailers)) => {
+                    self.trailers = Some(trailers);
+                    Some(Vec::new())
+                },
+                Some(StreamBodyEvent::End) => None,
+                Some(StreamBodyEvent::Reset) => return Err(BodyError::StreamReset),
+                None => return Err(BodyError::ConnectionClosed)
+            }
+        };
+        if chunk.is_none() {
+            self.finish();
+        }
+        Ok(chunk)
+    }
+
+    fn finish(&mut self) {
         self.consumed = true;
-        &self.buffer
+        if let Some(BodySource::Connection(decoder)) = &mut self.source {
+            self.trailers = Some(std::mem::take(&mut decoder.trailers));
+        }
+        if self.trailers.is_none() {
+            self.trailers = Some(HeaderMap::new());
+        }
+        self.release_reader();
+    }
+
+    /// Hands the underlying connection reader back to the protocol, so that the next
+    /// request on a persistent connection can be parsed from it. Whatever part of the
+    /// body wasn't read is drained by the receiving side.
+    fn release_reader(&mut self) {
+        let Some(release) = self.release.take() else {
+            return;
+        };
+        if let Some(BodySource::Connection(decoder)) = self.source.take() {
+            let _ = release.send(ReleasedReader { decoder });
+        }
     }
-}
\ No newline at end of file
+}
+
+impl Drop for LazyBodyReader {
+    fn drop(&mut self) {
+        self.release_reader();
+    }
+}
+
+pub struct ReleasedReader {
+    decoder: BodyDecoder
+}
+
+impl ReleasedReader {
+    /// Discards the unread remainder of the body and returns the reader positioned at
+    /// the start of the next request.
+    pub(crate) async fn drain(mut self) -> Result<ConnectionReader, BodyError> {
+        while self.decoder.next_chunk().await?.is_some() {}
+        Ok(self.decoder.reader)
+    }
+}
+
+struct BodyDecoder {
+    reader: ConnectionReader,
+    state: DecoderState,
+    timeout: Duration,
+    limits: ParserLimits,
+    /// How much of the body the chunks read so far take up
+    chunked_size: usize,
+    trailers: HeaderMap
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum DecoderState {
+    Length(usize),
+    ChunkSize,
+    ChunkData(usize),
+    Done
+}
+
+impl BodyDecoder {
+    fn new(reader: ConnectionReader, framing: BodyFraming, timeout: Duration, limits: ParserLimits) -> Self {
+        let state = match framing {
+            BodyFraming::Empty | BodyFraming::Length(0) => DecoderState::Done,
+            BodyFraming::Length(length) => DecoderState::Length(length),
+            BodyFraming::Chunked => DecoderState::ChunkSize
+        };
+        BodyDecoder {
+            reader,
+            state,
+            timeout,
+            limits,
+            chunked_size: 0,
+            trailers: HeaderMap::new()
+        }
+    }
+
+    /// Returns the next piece of body data, or `None` once the body is over. A chunk
+    /// header or a zero-sized chunk followed by trailers may produce an empty piece.
+    async fn next_chunk(&mut self) -> Result<Option<Vec<u8>>, BodyError> {
+        let timeout = self.timeout;
+        tokio::time::timeout(timeout, self.decode_chunk()).await.map_err(|_| BodyError::TimedOut)?
+    }
+
+    async fn decode_chunk(&mut self) -> Result<Option<Vec<u8>>, BodyError> {
+        match self.state {
+            DecoderState::Done => Ok(None),
+            DecoderState::Length(remaining) => {
+                let data = self.read_data(remaining).await?;
+                self.state = match remaining - data.len() {
+                    0 => DecoderState::Done,
+                    left => DecoderState::Length(left)
+                };
+                Ok(Some(data))
+            },
+            DecoderState::ChunkSize => {
+                let size = self.read_chunk_size().await?;
+                self.chunked_size = self.chunked_size.saturating_add(size);
+                if self.chunked_size > self.limits.max_body_size {
+                    return Err(BodyError::TooLarge);
+                }
+                if size == 0 {
+                    self.trailers = read_header_fields(&mut self.reader, &self.limits)
+                        .await
+                        .map_err(|_| BodyError::InvalidTrailer)?;
+                    self.state = DecoderState::Done;
+                    return Ok(None);
+                }
+                self.state = DecoderState::ChunkData(size);
+                Ok(Some(Vec::new()))
+            },
+            DecoderState::ChunkData(remaining) => {
+                let data = self.read_data(remaining).await?;
+                self.state = match remaining - data.len() {
+                    0 => {
+                        self.read_chunk_terminator().await?;
+                        DecoderState::ChunkSize
+                    },
+                    left => DecoderState::ChunkData(left)
+                };
+                Ok(Some(data))
+            }
+        }
+    }
+
+    async fn read_data(&mut self, remaining: usize) -> Result<Vec<u8>, BodyError> {
+        let mut data = vec![0; remaining.min(MAX_READ_SIZE)];
+        let read = self.reader.read(&mut data).await?;
+        if read == 0 {
+            return Err(BodyError::ConnectionClosed);
+        }
+        data.truncate(read);
+        Ok(data)
+    }
+
+    /// Parses a `chunk-size [ chunk-ext ] CRLF` line. Chunk extensions carry no meaning
+    /// for us, so they're skipped once the line is known to be well-formed.
+    async fn read_chunk_size(&mut self) -> Result<usize, BodyError> {
+        let mut line = Vec::new();
+        let read = (&mut self.reader)
+            .take(MAX_CHUNK_SIZE_LINE as u64)
+            .read_until(b'\n', &mut line)
+            .await?;
+        if read == 0 {
+            return Err(BodyError::ConnectionClosed);
+        }
+        if !line.ends_with(b"\r\n") {
+            return Err(BodyError::InvalidChunkSize);
+        }
+
+        let line = std::str::from_utf8(&line[..line.len() - 2]).map_err(|_| BodyError::InvalidChunkSize)?;
+        let (size, extensions) = line.split_once(';').unwrap_or((line, ""));
+        let size = size.trim_end_matches([' ', '\t']);
+        if size.is_empty() || !size.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            return Err(BodyError::InvalidChunkSize);
+        }
+        if extensions.contains(['\r', '\n']) {
+            return Err(BodyError::InvalidChunkSize);
+        }
+        usize::from_str_radix(size, 16).map_err(|_| BodyError::InvalidChunkSize)
+    }
+
+    async fn read_chunk_terminator(&mut self) -> Result<(), BodyError> {
+        let mut terminator = [0; 2];
+        self.reader.read_exact(&mut terminator).await?;
+        if &terminator != b"\r\n" {
+            return Err(BodyError::InvalidChunkTerminator);
+        }
+        Ok(())
+    }
+}
@@ -1,4 +1,5 @@
 pub mod alpha;
+pub mod beta;
 pub mod body;
 
 #[allow(dead_code)]
@@ -11,11 +12,18 @@ pub enum HttpParserError {
     InvalidStream,
     InvalidHeader,
     InvalidContentLength,
+    UnsupportedTransferEncoding,
+    AmbiguousBodyLength,
     InvalidRequestLine,
+    InvalidVersion,
     FrameHeader,
+    FrameSize,
     FramePayload,
     HeaderLine,
     UnknownFrameType,
     HeaderDecoding,
     RequiredHeaderNotFound,
+    UriTooLong,
+    HeadersTooLarge,
+    BodyTooLarge,
 }
\ No newline at end of file
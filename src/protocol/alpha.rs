@@ -1,44 +1,76 @@
-use std::panic::AssertUnwindSafe;
 use crate::encoder::alpha::AlphaHttpEncoder;
+use crate::encoder::beta::BetaHttpEncoder;
 use crate::encoder::format::{DefaultHttpResponseFormatter, HttpResponseFormatter};
+use crate::handle::{ServerHandle, ShutdownSignal};
 use crate::headers;
-use crate::parser::alpha::AlphaHttpParser;
-use crate::protocol::{HttpProtocol, HttpProtocolError};
+use crate::listener::{ListenerConfig, ListenerInfo};
+use crate::parser::alpha::{AlphaHttpParser, INITIAL_BUFFER_SIZE};
+use crate::parser::beta::{parse_http2_settings, BetaHttpParser, Setting, CONNECTION_PREFACE};
+use crate::parser::body::ReleasedReader;
+use crate::protocol::beta::{BetaConnectionContext, UpgradedRequest};
+use crate::protocol::listener::{drain_connections, AcceptConfig, BoundListener};
+use crate::protocol::stream::{ConnectionAcceptor, ConnectionReader, ConnectionStream, ConnectionWriter, Rewind, WriteTimeout};
+use crate::protocol::{beta, route_request, ConnectionInfo, HttpProtocol, HttpProtocolError};
+use crate::request::{HttpHeader, HeaderMap, HttpMethod, HttpRequest, HttpScheme, HttpVersion};
 use crate::response::{HttpResponse, HttpStatusCode};
-use crate::router::{HttpRouter, Router};
+use crate::response::body::HttpBody;
+use crate::response::error::{default_error_handler, ErrorHandler, RequestError};
+use crate::router::Router;
+use crate::timeouts::Timeouts;
+use crate::limits::{ConnectionLimiter, ConnectionLimits, ParserLimits};
 use anyhow::bail;
 use async_trait::async_trait;
 use std::sync::Arc;
-use futures::FutureExt;
-use tokio::io::AsyncWriteExt;
-use tokio::net::TcpListener;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::sync::oneshot;
+
+/// Sent to connections over a limit when the server rejects them.
+const SERVICE_UNAVAILABLE: &[u8] = b"HTTP/1.1 503 Service Unavailable\r\nContent-Type: text/plain\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+
+/// How long, and how much of, what the client keeps sending is discarded before closing
+/// the connection on a refused request
+const LINGER_TIMEOUT: Duration = Duration::from_secs(1);
+const LINGER_LIMIT: u64 = 1024 * 1024;
 
 pub struct AlphaHttpProtocol {
-    socket: Option<TcpListener>,
-    router: Arc<Option<Router>>,
+    listeners: Vec<BoundListener>,
+    acceptor: ConnectionAcceptor,
+    router: Option<Arc<Router>>,
     parser: Arc<AlphaHttpParser>,
     encoder: Arc<AlphaHttpEncoder>,
-    formatter: Arc<Box<dyn HttpResponseFormatter + Send + Sync>>
+    formatter: Arc<Box<dyn HttpResponseFormatter + Send + Sync>>,
+    timeouts: Timeouts,
+    limits: ConnectionLimits,
+    error_handler: ErrorHandler
 }
 
 impl AlphaHttpProtocol {
     pub fn new() -> Self {
         AlphaHttpProtocol {
-            socket: None,
-            router: Arc::new(None),
+            listeners: Vec::new(),
+            acceptor: ConnectionAcceptor::default(),
+            router: None,
             parser: Arc::new(AlphaHttpParser::new()),
             encoder: Arc::new(AlphaHttpEncoder::new()),
-            formatter: Arc::new(Box::new(DefaultHttpResponseFormatter::new()))
+            formatter: Arc::new(Box::new(DefaultHttpResponseFormatter::new())),
+            timeouts: Timeouts::default(),
+            limits: ConnectionLimits::default(),
+            error_handler: default_error_handler()
         }
     }
 
     pub fn link_router(router: Router) -> Self {
         AlphaHttpProtocol {
-            socket: None,
-            router: Arc::new(Some(router)),
+            listeners: Vec::new(),
+            acceptor: ConnectionAcceptor::default(),
+            router: Some(Arc::new(router)),
             parser: Arc::new(AlphaHttpParser::new()),
             encoder: Arc::new(AlphaHttpEncoder::new()),
-            formatter: Arc::new(Box::new(DefaultHttpResponseFormatter::new()))
+            formatter: Arc::new(Box::new(DefaultHttpResponseFormatter::new())),
+            timeouts: Timeouts::default(),
+            limits: ConnectionLimits::default(),
+            error_handler: default_error_handler()
         }
     }
 }
@@ -46,96 +78,557 @@ impl AlphaHttpProtocol {
 #[async_trait]
 impl HttpProtocol for AlphaHttpProtocol {
     fn set_router(&mut self, router: Router) {
-        self.router = Arc::new(Some(router))
+        self.router = Some(Arc::new(router))
+    }
+
+    fn set_timeouts(&mut self, timeouts: Timeouts) {
+        self.timeouts = timeouts;
+    }
+
+    fn set_connection_limits(&mut self, limits: ConnectionLimits) {
+        self.limits = limits;
+    }
+
+    fn set_parser_limits(&mut self, limits: ParserLimits) {
+        self.parser = Arc::new(AlphaHttpParser::with_limits(limits));
+    }
+
+    fn set_error_handler(&mut self, handler: ErrorHandler) {
+        self.error_handler = handler;
+    }
+
+    #[cfg(feature = "tls")]
+    fn set_tls(&mut self, config: crate::tls::TlsConfig) -> crate::Result<()> {
+        let protocols = [crate::tls::ALPN_HTTP2, crate::tls::ALPN_HTTP1];
+        self.acceptor.tls = Some(config.acceptor(&protocols)?);
+        Ok(())
     }
 
     async fn connect(&mut self, addr: &str) -> crate::Result<()> {
-        let socket = TcpListener::bind(addr).await.map_err(|_| HttpProtocolError::PortAlreadyInUse)?;
-        self.socket = Some(socket);
+        self.listeners.push(BoundListener::bind_tcp(addr).await?);
+        Ok(())
+    }
+
+    async fn add_listener(&mut self, config: ListenerConfig) -> crate::Result<()> {
+        let listener = BoundListener::bind(
+            config,
+            #[cfg(feature = "tls")]
+            &[crate::tls::ALPN_HTTP2, crate::tls::ALPN_HTTP1]
+        ).await?;
+        self.listeners.push(listener);
         Ok(())
     }
 
-    async fn listen(&'static self) -> crate::Result<()> {
-        let socket = match &self.socket {
-            Some(socket) => socket,
-            None => bail!(HttpProtocolError::UnboundSocket)
+    fn listeners(&self) -> Vec<ListenerInfo> {
+        self.listeners.iter().map(|listener| listener.info.clone()).collect()
+    }
+
+    async fn listen(self: Box<Self>, handle: ServerHandle, shutdown_timeout: Duration) -> crate::Result<()> {
+        if self.listeners.is_empty() {
+            bail!(HttpProtocolError::UnboundSocket)
+        }
+        let Some(router) = self.router.clone() else {
+            bail!(HttpProtocolError::UnsetRouter)
+        };
+        let AlphaHttpProtocol { listeners, acceptor, parser, encoder, formatter, timeouts, limits, error_handler, .. } = *self;
+        let config = AcceptConfig {
+            acceptor,
+            handshake_timeout: timeouts.request_head,
+            limiter: Arc::new(ConnectionLimiter::new(limits, handle.tracker())),
+            rejection: Some(SERVICE_UNAVAILABLE)
         };
 
-        loop {
-            let (stream, addr) = match socket.accept().await {
-                Ok(connection) => connection,
-                Err(e) => {
-                    eprintln!("Failed to accept connection: {}", e);
-                    continue;
-                }
-            };
-            if let Err(err) = stream.set_nodelay(true) {
+        let shutdown = handle.signal();
+        let serve = move |stream, connection| serve_stream(stream, AlphaConnectionContext {
+            connection,
+            router: router.clone(),
+            parser: parser.clone(),
+            encoder: encoder.clone(),
+            formatter: formatter.clone(),
+            shutdown: shutdown.clone(),
+            timeouts,
+            error_handler: error_handler.clone()
+        });
+        let accepting = listeners
+            .into_iter()
+            .map(|listener| listener.accept_connections(&config, handle.signal(), serve.clone()));
+        let connections = futures::future::join_all(accepting).await;
+        drain_connections(connections, shutdown_timeout).await;
+        Ok(())
+    }
+}
+
+pub(crate) struct AlphaConnectionContext {
+    pub(crate) connection: Arc<ConnectionInfo>,
+    pub(crate) router: Arc<Router>,
+    pub(crate) parser: Arc<AlphaHttpParser>,
+    pub(crate) encoder: Arc<AlphaHttpEncoder>,
+    pub(crate) formatter: Arc<Box<dyn HttpResponseFormatter + Send + Sync>>,
+    pub(crate) shutdown: ShutdownSignal,
+    pub(crate) timeouts: Timeouts,
+    pub(crate) error_handler: ErrorHandler
+}
+
+/// Serves a connection over HTTP/1.1, or over HTTP/2 if it was agreed on through ALPN.
+pub(crate) async fn serve_stream(stream: ConnectionStream, context: AlphaConnectionContext) {
+    let http2 = stream.alpn_protocol() == Some(b"h2");
+    let (read_half, write_half) = tokio::io::split(stream);
+    let reader = BufReader::with_capacity(INITIAL_BUFFER_SIZE, Rewind::new(read_half));
+    if http2 {
+        beta::serve_connection(reader, write_half, http2_context(context), None).await;
+        return;
+    }
+    serve_connection(reader, write_half, context).await;
+}
+
+/// Serves requests from a single connection until the client asks to close it, stays
+/// idle for longer than the keep-alive timeout or an unrecoverable error happens. Clients
+/// that take too long to send a request head are answered with `408 Request Timeout`.
+/// Clients that start with the HTTP/2 connection preface, or upgrade to h2c, are served
+/// over HTTP/2. Once the server starts shutting down, the request in flight is answered
+/// with `Connection: close`, and idle connections are closed right away.
+async fn serve_connection(mut reader: ConnectionReader, mut write_half: ConnectionWriter, mut context: AlphaConnectionContext) {
+    let timeouts = context.timeouts;
+    if next_request_start(&mut reader, timeouts.keep_alive, &mut context.shutdown).await.is_none() {
+        return;
+    }
+    let (http2, mut reader) = detect_http2_preface(reader, timeouts.request_head).await;
+    if http2 {
+        beta::serve_connection(reader, write_half, http2_context(context), None).await;
+        return;
+    }
+    loop {
+        if next_request_start(&mut reader, timeouts.keep_alive, &mut context.shutdown).await.is_none() {
+            return;
+        }
+
+        #[cfg(feature = "logging")]
+        let start_time = std::time::Instant::now();
+        let parsing = context.parser.parse(&context.connection, reader, timeouts.request_body);
+        let (parsed, released) = match tokio::time::timeout(timeouts.request_head, parsing).await {
+            Ok(Ok(request)) => request,
+            Ok(Err((e, reader))) => {
                 #[cfg(feature = "logging")]
-                tracing::warn!("Failed to set TCP_NODELAY: {}", err);
-            }
-            let router = self.router.clone();
-            let parser = self.parser.clone();
-            let encoder = self.encoder.clone();
-            let formatter = self.formatter.clone();
-
-            tokio::spawn(async move {
-                let start_time = std::time::Instant::now();
-                let (read_half, mut write_half) = stream.into_split();
-                let parsed = match parser.parse(addr, read_half).await {
-                    Ok(request) => request,
-                    Err(e) => {
-                        eprintln!("Failed to parse request: {:?}", e);
-                        return;
-                    }
-                };
-
-                let router = Option::as_ref(&*router).expect("Router not set");
-
-                let routed_response = async { AssertUnwindSafe(router.route(parsed)).catch_unwind().await }.await;
-                let response: HttpResponse = match routed_response {
-                    Ok(response) => response.unwrap_or_else(|| HttpResponse {
-                        status_code: HttpStatusCode::NotFound,
-                        headers: headers! {
-                                ContentType: "text/plain"
-                            },
-                        body: vec![]
-                    }),
-                    Err(e) => {
-                        eprintln!("Failed to route request: {:?}", e);
-                        HttpResponse {
-                            status_code: HttpStatusCode::InternalServerError,
-                            headers: headers! {
-                                ContentType: "text/plain"
-                            },
-                            body: vec![]
-                        }
-                    }
-                };
-
-                let formatted = formatter.format(response);
-                let encoded = match encoder.encode(formatted) {
-                    Ok(encoded) => encoded,
-                    Err(e) => {
-                        eprintln!("Failed to encode response: {}", e);
-                        return;
-                    }
-                };
-
-                if let Err(e) = write_half.write_all(&encoded).await {
-                    eprintln!("Failed to write response: {}", e);
-                    return;
+                tracing::debug!("Failed to parse request from {}: {:?}", context.connection.peer_addr, e);
+                if let Some(error) = RequestError::from_parser_error(&e) {
+                    refuse_request(&context, error, &mut write_half, Some(reader)).await;
                 }
+                return;
+            },
+            Err(_) => {
+                refuse_request(&context, RequestError::timed_out(), &mut write_half, None).await;
+                return;
+            }
+        };
+        // h2c is only ever negotiated this way in cleartext, TLS relies on ALPN instead
+        let upgrade = match context.connection.scheme {
+            HttpScheme::HTTP => h2c_upgrade_settings(&parsed.headers),
+            HttpScheme::HTTPS => None
+        };
+        if let Some(settings) = upgrade {
+            let encoder = context.encoder.clone();
+            switch_to_http2(parsed, released, settings, write_half, encoder, http2_context(context)).await;
+            return;
+        }
+        let client_keep_alive = parsed.keep_alive;
+        let head_only = parsed.method == HttpMethod::HEAD;
+        let version = parsed.version;
 
-                if let Err(e) = write_half.flush().await {
-                    eprintln!("Failed to flush response: {}", e);
-                    return;
-                }
-                #[cfg(feature = "logging")]
-                tracing::debug!("Request from {} took {:?}", addr, start_time.elapsed());
-            });
+        let response = route_request(&context.router, parsed).await;
+        let mut formatted = context.formatter.format(response);
+        // HTTP/1.0 clients don't know chunked coding, so bodies of unknown length are
+        // delimited by closing the connection instead
+        let close_delimited = version == HttpVersion::Http10 && formatted.headers.remove(&HttpHeader::TransferEncoding).is_some();
+        let keep_alive = client_keep_alive && !close_delimited && !has_close_option(&formatted.headers) && !context.shutdown.is_triggered();
+        let connection_option = if keep_alive { "keep-alive" } else { "close" };
+        formatted.headers.insert(HttpHeader::Connection, connection_option.to_string());
+
+        if let Err(e) = write_response(&context.encoder, formatted, &mut write_half, timeouts.response_write, head_only).await {
+            eprintln!("Failed to write response: {}", e);
+            return;
+        }
+        #[cfg(feature = "logging")]
+        tracing::debug!("Request from {} took {:?}", context.connection.peer_addr, start_time.elapsed());
+
+        if !keep_alive {
+            // Bodies delimited by the end of the connection are only complete once it's shut down cleanly
+            let _ = write_half.shutdown().await;
+            return;
         }
+
+        reader = match released.await {
+            Ok(released) => match released.drain().await {
+                Ok(reader) => reader,
+                Err(_) => return
+            },
+            Err(_) => return
+        };
+    }
+}
+
+/// Answers a request the server refused, then closes the connection. Whatever the client is
+/// still sending is discarded for a moment first, since closing a socket with unread data
+/// resets it, which can make the client lose the response.
+async fn refuse_request(
+    context: &AlphaConnectionContext,
+    error: RequestError,
+    write_half: &mut ConnectionWriter,
+    reader: Option<ConnectionReader>
+) {
+    let mut formatted = context.formatter.format((context.error_handler)(&error));
+    formatted.headers.insert(HttpHeader::Connection, "close".to_string());
+    if let Err(e) = write_response(&context.encoder, formatted, write_half, context.timeouts.response_write, false).await {
+        eprintln!("Failed to write response: {}", e);
+        return;
+    }
+    let _ = write_half.shutdown().await;
+    if let Some(reader) = reader {
+        let mut remaining = reader.take(LINGER_LIMIT);
+        let _ = tokio::time::timeout(LINGER_TIMEOUT, tokio::io::copy(&mut remaining, &mut tokio::io::sink())).await;
+    }
+}
+
+/// Writes and flushes a response, giving up if the client stops reading it for longer
+/// than `timeout`. Only the head is written for `HEAD` requests.
+async fn write_response(
+    encoder: &AlphaHttpEncoder,
+    response: HttpResponse,
+    write_half: &mut ConnectionWriter,
+    timeout: Duration,
+    head_only: bool
+) -> crate::Result<()> {
+    let mut writer = WriteTimeout::new(write_half, timeout);
+    if head_only {
+        encoder.write_head(response, &mut writer).await?;
+    } else {
+        encoder.write(response, &mut writer).await?;
+    }
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Waits for the next request to start arriving. Returns `None` if the connection is closed
+/// or stays idle for longer than `keep_alive`, or the server starts shutting down first.
+async fn next_request_start<'a>(
+    reader: &'a mut ConnectionReader,
+    keep_alive: Duration,
+    shutdown: &mut ShutdownSignal
+) -> Option<&'a [u8]> {
+    tokio::select! {
+        biased;
+        buffer = tokio::time::timeout(keep_alive, reader.fill_buf()) => match buffer {
+            Ok(Ok(buffer)) if !buffer.is_empty() => Some(buffer),
+            _ => None
+        },
+        _ = shutdown.triggered() => None
+    }
+}
+
+/// Whether the connection starts with the HTTP/2 preface. No HTTP/1.1 request can start
+/// like it, since `PRI` is reserved as a method, but one such as `POST` may share its first
+/// bytes, so it keeps reading until they stop matching or the whole preface has arrived.
+/// Whatever was read is handed back with the reader. Connections that close or stall
+/// before that are left to the HTTP/1.1 parser, which answers them as usual.
+async fn detect_http2_preface(reader: ConnectionReader, timeout: Duration) -> (bool, ConnectionReader) {
+    if let Some(http2) = matches_preface(reader.buffer()) {
+        return (http2, reader);
     }
+    let mut received = reader.buffer().to_vec();
+    let mut stream = reader.into_inner();
+    let reading = async {
+        loop {
+            let mut chunk = [0; CONNECTION_PREFACE.len()];
+            let missing = CONNECTION_PREFACE.len() - received.len();
+            match stream.read(&mut chunk[..missing]).await {
+                Ok(0) | Err(_) => return false,
+                Ok(read) => received.extend_from_slice(&chunk[..read])
+            }
+            if let Some(http2) = matches_preface(&received) {
+                return http2;
+            }
+        }
+    };
+    let http2 = tokio::time::timeout(timeout, reading).await.unwrap_or(false);
+    stream.rewind(received);
+    (http2, BufReader::with_capacity(INITIAL_BUFFER_SIZE, stream))
+}
+
+/// Whether the bytes are the HTTP/2 preface, or `None` if they match it as far as they go
+/// but more are needed to tell.
+fn matches_preface(buffer: &[u8]) -> Option<bool> {
+    let length = buffer.len().min(CONNECTION_PREFACE.len());
+    if buffer[..length] != CONNECTION_PREFACE[..length] {
+        return Some(false);
+    }
+    (length == CONNECTION_PREFACE.len()).then_some(true)
+}
+
+fn http2_context(context: AlphaConnectionContext) -> BetaConnectionContext {
+    BetaConnectionContext {
+        connection: context.connection,
+        router: context.router,
+        parser: Arc::new(BetaHttpParser::new()),
+        encoder: Arc::new(BetaHttpEncoder::new()),
+        formatter: context.formatter,
+        shutdown: context.shutdown,
+        timeouts: context.timeouts,
+        parser_limits: context.parser.limits(),
+        error_handler: context.error_handler
+    }
+}
+
+/// The client's HTTP/2 settings if the request asks to upgrade to h2c. Requests that are
+/// missing any part of the upgrade, or have malformed settings, are served over HTTP/1.1.
+fn h2c_upgrade_settings(headers: &HeaderMap) -> Option<Vec<Setting>> {
+    let has_option = |header: HttpHeader, token: &str| headers.get_all(&header).any(|value| has_token(value, token));
+    if !has_option(HttpHeader::Upgrade, "h2c") || !has_option(HttpHeader::Connection, "upgrade") || !has_option(HttpHeader::Connection, "http2-settings") {
+        return None;
+    }
+    parse_http2_settings(headers.get(&HttpHeader::Http2Settings)?)
+}
+
+/// Answers with 101 Switching Protocols and serves the rest of the connection over HTTP/2,
+/// where the request that asked for it gets its response on stream 1. Its body is read in
+/// full beforehand, since HTTP/2 frames follow right after it.
+async fn switch_to_http2(
+    mut request: HttpRequest,
+    released: oneshot::Receiver<ReleasedReader>,
+    settings: Vec<Setting>,
+    mut write_half: ConnectionWriter,
+    encoder: Arc<AlphaHttpEncoder>,
+    context: BetaConnectionContext
+) {
+    if let Err(e) = request.body_reader.detach().await {
+        eprintln!("Failed to read upgrade request body: {:?}", e);
+        return;
+    }
+    let reader = match released.await {
+        Ok(released) => match released.drain().await {
+            Ok(reader) => reader,
+            Err(_) => return
+        },
+        Err(_) => return
+    };
+
+    let switching = HttpResponse {
+        status_code: HttpStatusCode::SwitchingProtocols,
+        headers: headers! {
+            Connection: "Upgrade",
+            Upgrade: "h2c"
+        },
+        body: HttpBody::empty()
+    };
+    if let Err(e) = write_response(&encoder, switching, &mut write_half, context.timeouts.response_write, false).await {
+        eprintln!("Failed to write response: {}", e);
+        return;
+    }
+
+    let upgraded = UpgradedRequest { settings, request };
+    beta::serve_connection(reader, write_half, context, Some(upgraded)).await;
+}
+
+fn has_close_option(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(&HttpHeader::Connection)
+        .any(|value| has_token(value, "close"))
+}
+
+fn has_token(value: &str, token: &str) -> bool {
+    value.split(',').any(|option| option.trim().eq_ignore_ascii_case(token))
 }
 
 unsafe impl Send for AlphaHttpProtocol {}
-unsafe impl Sync for AlphaHttpProtocol {}
\ No newline at end of file
+unsafe impl Sync for AlphaHttpProtocol {}
+#[cfg(test)]
+mod tests {
+    use crate::request::HttpHeader;
+    use crate::response::body::HttpBody;
+    use crate::response::HttpStatusCode;
+    use crate::router::func::function_handler;
+    use crate::router::{HttpRouter, Router};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+
+    fn echo_router() -> Arc<Router> {
+        Arc::new(Router::new().add_handler("/echo", function_handler(|mut request| async move {
+            String::from_utf8_lossy(request.read_body().await.unwrap()).into_owned()
+        })))
+    }
+
+    /// Sends the pieces with a pause in between, so that they arrive in separate reads.
+    async fn connect_in_pieces(router: Arc<Router>, pieces: &[&[u8]]) -> DuplexStream {
+        let (mut client, server) = tokio::io::duplex(4096);
+        tokio::spawn(crate::serve_connection(server, router));
+        for piece in pieces {
+            client.write_all(piece).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        client
+    }
+
+    async fn exchange(router: Arc<Router>, requests: &[u8]) -> String {
+        let mut client = connect_in_pieces(router, &[requests]).await;
+        let mut responses = String::new();
+        client.read_to_string(&mut responses).await.unwrap();
+        responses
+    }
+
+    fn hello_router() -> Arc<Router> {
+        Arc::new(Router::new()
+            .add_handler("/hello", function_handler(|_| async { "hello" }))
+            .add_handler("/stream", function_handler(|_| async {
+                HttpBody::stream(futures::stream::iter(["hel", "lo"]))
+            })))
+    }
+
+    #[tokio::test]
+    async fn head_response_of_any_method_handler_has_no_body() {
+        let responses = exchange(hello_router(), b"HEAD /hello HTTP/1.1\r\n\r\nGET /hello HTTP/1.1\r\nConnection: close\r\n\r\n").await;
+        let (head, get) = responses.split_at(responses.rfind("HTTP/1.1 200").unwrap());
+        assert!(head.starts_with("HTTP/1.1 200"), "{responses}");
+        assert!(head.contains("Content-Length: 5\r\n"), "{head}");
+        assert!(head.ends_with("\r\n\r\n"), "{head}");
+        assert!(get.ends_with("\r\n\r\nhello"), "{get}");
+    }
+
+    #[tokio::test]
+    async fn head_response_of_streamed_body_keeps_its_framing() {
+        let response = exchange(hello_router(), b"HEAD /stream HTTP/1.1\r\nConnection: close\r\n\r\n").await;
+        assert!(response.contains("Transfer-Encoding: chunked\r\n"), "{response}");
+        assert!(!response.contains("Content-Length"), "{response}");
+        assert!(response.ends_with("\r\n\r\n"), "{response}");
+    }
+
+    #[tokio::test]
+    async fn body_of_unknown_length_is_delimited_by_closing_for_http10() {
+        let response = exchange(hello_router(), b"GET /stream HTTP/1.0\r\nConnection: keep-alive\r\n\r\n").await;
+        assert!(!response.contains("Transfer-Encoding"), "{response}");
+        assert!(response.contains("Connection: close\r\n"), "{response}");
+        assert!(response.ends_with("\r\n\r\nhello"), "{response}");
+    }
+
+    #[tokio::test]
+    async fn body_of_unknown_length_is_chunked_for_http11() {
+        let response = exchange(hello_router(), b"GET /stream HTTP/1.1\r\nConnection: close\r\n\r\n").await;
+        assert!(response.contains("Transfer-Encoding: chunked\r\n"), "{response}");
+        assert!(response.ends_with("\r\n\r\n3\r\nhel\r\n2\r\nlo\r\n0\r\n\r\n"), "{response}");
+    }
+
+    #[tokio::test]
+    async fn not_modified_response_has_no_body() {
+        let router = Arc::new(Router::new().add_handler("/cached", function_handler(|_| async {
+            (HttpStatusCode::NotModified, "stale")
+        })));
+        let responses = exchange(router, b"GET /cached HTTP/1.1\r\n\r\nGET /cached HTTP/1.1\r\nConnection: close\r\n\r\n").await;
+        assert_eq!(bodies(&responses), ["", ""], "{responses}");
+        assert!(!responses.contains("Content-Length") && !responses.contains("Transfer-Encoding"), "{responses}");
+    }
+
+    /// The bodies of every response, in order.
+    fn bodies(responses: &str) -> Vec<&str> {
+        responses.split("HTTP/1.1 ")
+            .skip(1)
+            .map(|response| response.split_once("\r\n\r\n").unwrap().1)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn pipelined_chunked_requests_are_served_in_order() {
+        let responses = exchange(echo_router(), b"\
+            POST /echo HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3;name=value\r\nhel\r\n2\r\nlo\r\n0\r\n\r\n\
+            POST /echo HTTP/1.1\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n5\r\nworld\r\n0\r\n\r\n").await;
+        assert_eq!(bodies(&responses), ["hello", "world"], "{responses}");
+    }
+
+    #[tokio::test]
+    async fn trailers_follow_the_last_chunk() {
+        let router = Arc::new(Router::new().add_handler("/checksum", function_handler(|mut request| async move {
+            request.read_body().await.unwrap();
+            let trailers = request.body_reader.trailers().unwrap();
+            trailers.get(&HttpHeader::from_name("X-Checksum")).unwrap_or("missing").to_string()
+        })));
+        let responses = exchange(router, b"\
+            POST /checksum HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\nX-Checksum: 42\r\n\r\n\
+            POST /checksum HTTP/1.1\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n0\r\n\r\n").await;
+        assert_eq!(bodies(&responses), ["42", "missing"], "{responses}");
+    }
+
+    #[tokio::test]
+    async fn content_length_and_transfer_encoding_together_are_refused() {
+        // Were the chunked body taken as 3 bytes, the rest would be smuggled as another request
+        let responses = exchange(echo_router(), b"\
+            POST /echo HTTP/1.1\r\nContent-Length: 3\r\nTransfer-Encoding: chunked\r\n\r\n\
+            0\r\n\r\nGET /echo HTTP/1.1\r\n\r\n").await;
+        assert!(responses.starts_with("HTTP/1.1 400"), "{responses}");
+        assert_eq!(responses.matches("HTTP/1.1 ").count(), 1, "{responses}");
+    }
+
+    #[tokio::test]
+    async fn invalid_content_length_is_refused() {
+        for length in ["abc", "-1", "+5", "5 5", "", "99999999999999999999999"] {
+            let request = format!("POST /echo HTTP/1.1\r\nContent-Length: {length}\r\n\r\nhello");
+            let response = exchange(echo_router(), request.as_bytes()).await;
+            assert!(response.starts_with("HTTP/1.1 400"), "{length:?}: {response}");
+        }
+    }
+
+    #[tokio::test]
+    async fn repeated_content_length_must_agree() {
+        let response = exchange(echo_router(), b"POST /echo HTTP/1.1\r\nContent-Length: 5\r\nContent-Length: 6\r\n\r\nhello!").await;
+        assert!(response.starts_with("HTTP/1.1 400"), "{response}");
+        let response = exchange(echo_router(), b"POST /echo HTTP/1.1\r\nContent-Length: 5, 6\r\n\r\nhello!").await;
+        assert!(response.starts_with("HTTP/1.1 400"), "{response}");
+
+        let responses = exchange(echo_router(), b"\
+            POST /echo HTTP/1.1\r\nContent-Length: 5\r\nContent-Length: 5\r\n\r\nhello\
+            POST /echo HTTP/1.1\r\nContent-Length: 5, 5\r\nConnection: close\r\n\r\nworld").await;
+        assert_eq!(bodies(&responses), ["hello", "world"], "{responses}");
+    }
+
+    #[tokio::test]
+    async fn unread_body_is_skipped_before_the_next_request() {
+        let responses = exchange(hello_router(), b"\
+            POST /hello HTTP/1.1\r\nContent-Length: 5\r\n\r\nGET /\
+            POST /hello HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nGET /\r\n0\r\n\r\n\
+            GET /hello HTTP/1.1\r\nConnection: close\r\n\r\n").await;
+        assert_eq!(bodies(&responses), ["hello", "hello", "hello"], "{responses}");
+    }
+
+    #[tokio::test]
+    async fn connection_persistence_follows_the_version_and_options() {
+        let requests: [(&[u8], usize); 4] = [
+            (b"GET /hello HTTP/1.1\r\n\r\nGET /hello HTTP/1.1\r\nConnection: close\r\n\r\n", 2),
+            (b"GET /hello HTTP/1.1\r\nConnection: Close\r\n\r\nGET /hello HTTP/1.1\r\n\r\n", 1),
+            (b"GET /hello HTTP/1.0\r\n\r\nGET /hello HTTP/1.1\r\n\r\n", 1),
+            (b"GET /hello HTTP/1.0\r\nConnection: keep-alive\r\n\r\nGET /hello HTTP/1.0\r\n\r\n", 2)
+        ];
+        for (requests, expected) in requests {
+            let responses = exchange(hello_router(), requests).await;
+            assert_eq!(responses.matches("hello").count(), expected, "{responses}");
+            let last = responses.rsplit("\r\n\r\n").nth(1).unwrap();
+            assert!(last.contains("Connection: close"), "{responses}");
+        }
+    }
+
+    #[tokio::test]
+    async fn request_line_split_within_the_preface_is_served_over_http1() {
+        let mut client = connect_in_pieces(echo_router(), &[
+            b"P",
+            b"OST /echo HTTP/1.1\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello"
+        ]).await;
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+        assert!(response.ends_with("hello"), "{response}");
+    }
+
+    #[tokio::test]
+    async fn preface_split_across_reads_is_served_over_http2() {
+        let mut client = connect_in_pieces(echo_router(), &[b"PRI * HTTP/2.0\r\n", b"\r\nSM\r\n\r\n"]).await;
+        let mut frame_header = [0; 9];
+        client.read_exact(&mut frame_header).await.unwrap();
+        // The server starts with its SETTINGS frame
+        assert_eq!(frame_header[3], 0x4);
+    }
+}
@@ -0,0 +1,1161 @@
+use crate::encoder::beta::BetaHttpEncoder;
+use crate::encoder::format::{DefaultHttpResponseFormatter, HttpResponseFormatter};
+use crate::handle::{ServerHandle, ShutdownSignal};
+use crate::hpack::{Decoder, Encoder, HeaderField, HpackError};
+use crate::listener::{ListenerConfig, ListenerInfo};
+use crate::parser::alpha::content_length;
+use crate::parser::beta::{BetaHttpParser, ErrorCode, Frame, Setting, DEFAULT_MAX_FRAME_SIZE};
+use crate::parser::body::{LazyBodyReader, StreamBodyEvent};
+use crate::parser::HttpParserError;
+use crate::protocol::listener::{drain_connections, AcceptConfig, BoundListener};
+use crate::protocol::stream::{ConnectionAcceptor, ConnectionStream, WriteTimeout};
+use crate::protocol::{route_request, ConnectionInfo, HttpProtocol, HttpProtocolError};
+use crate::request::{HttpHeader, HeaderMap, HttpMethod, HttpRequest, HttpScheme, HttpVersion};
+use crate::response::HttpResponse;
+use crate::response::error::{default_error_handler, ErrorHandler, RequestError};
+use crate::router::Router;
+use crate::timeouts::Timeouts;
+use crate::limits::{ConnectionLimiter, ConnectionLimits, ParserLimits};
+use anyhow::bail;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::sync::{mpsc, oneshot, OnceCell};
+use tokio::task::AbortHandle;
+use tokio::time::Instant;
+
+const MAX_CONCURRENT_STREAMS: u32 = 100;
+const DEFAULT_WINDOW_SIZE: i64 = 65535;
+const MAX_WINDOW_SIZE: i64 = (1 << 31) - 1;
+const MAX_FRAME_SIZE_LIMIT: u32 = (1 << 24) - 1;
+const MAX_HEADER_BLOCK_SIZE: usize = 65536;
+
+pub struct BetaHttpProtocol {
+    listeners: Vec<BoundListener>,
+    acceptor: ConnectionAcceptor,
+    router: Option<Arc<Router>>,
+    parser: Arc<BetaHttpParser>,
+    encoder: Arc<BetaHttpEncoder>,
+    formatter: Arc<Box<dyn HttpResponseFormatter + Send + Sync>>,
+    timeouts: Timeouts,
+    limits: ConnectionLimits,
+    parser_limits: ParserLimits,
+    error_handler: ErrorHandler
+}
+
+impl BetaHttpProtocol {
+    pub fn link_router(router: Router) -> Self {
+        BetaHttpProtocol {
+            listeners: Vec::new(),
+            acceptor: ConnectionAcceptor::default(),
+            router: Some(Arc::new(router)),
+            parser: Arc::new(BetaHttpParser::new()),
+            encoder: Arc::new(BetaHttpEncoder::new()),
+            formatter: Arc::new(Box::new(DefaultHttpResponseFormatter::new())),
+            timeouts: Timeouts::default(),
+            limits: ConnectionLimits::default(),
+            parser_limits: ParserLimits::default(),
+            error_handler: default_error_handler()
+        }
+    }
+}
+
+#[async_trait]
+impl HttpProtocol for BetaHttpProtocol {
+    fn set_router(&mut self, router: Router) {
+        self.router = Some(Arc::new(router))
+    }
+
+    fn set_timeouts(&mut self, timeouts: Timeouts) {
+        self.timeouts = timeouts;
+    }
+
+    fn set_connection_limits(&mut self, limits: ConnectionLimits) {
+        self.limits = limits;
+    }
+
+    fn set_parser_limits(&mut self, limits: ParserLimits) {
+        self.parser_limits = limits;
+    }
+
+    /// Only applies to requests over the parser limits, since malformed HTTP/2 requests are
+    /// answered with stream resets instead.
+    fn set_error_handler(&mut self, handler: ErrorHandler) {
+        self.error_handler = handler;
+    }
+
+    #[cfg(feature = "tls")]
+    fn set_tls(&mut self, config: crate::tls::TlsConfig) -> crate::Result<()> {
+        self.acceptor.tls = Some(config.acceptor(&[crate::tls::ALPN_HTTP2])?);
+        Ok(())
+    }
+
+    async fn connect(&mut self, addr: &str) -> crate::Result<()> {
+        self.listeners.push(BoundListener::bind_tcp(addr).await?);
+        Ok(())
+    }
+
+    async fn add_listener(&mut self, config: ListenerConfig) -> crate::Result<()> {
+        let listener = BoundListener::bind(
+            config,
+            #[cfg(feature = "tls")]
+            &[crate::tls::ALPN_HTTP2]
+        ).await?;
+        self.listeners.push(listener);
+        Ok(())
+    }
+
+    fn listeners(&self) -> Vec<ListenerInfo> {
+        self.listeners.iter().map(|listener| listener.info.clone()).collect()
+    }
+
+    async fn listen(self: Box<Self>, handle: ServerHandle, shutdown_timeout: Duration) -> crate::Result<()> {
+        if self.listeners.is_empty() {
+            bail!(HttpProtocolError::UnboundSocket)
+        }
+        let Some(router) = self.router.clone() else {
+            bail!(HttpProtocolError::UnsetRouter)
+        };
+        let BetaHttpProtocol { listeners, acceptor, parser, encoder, formatter, timeouts, limits, parser_limits, error_handler, .. } = *self;
+        let config = AcceptConfig {
+            acceptor,
+            handshake_timeout: timeouts.request_head,
+            limiter: Arc::new(ConnectionLimiter::new(limits, handle.tracker())),
+            rejection: None
+        };
+
+        let shutdown = handle.signal();
+        let serve = move |stream: ConnectionStream, connection| {
+            let context = BetaConnectionContext {
+                connection,
+                router: router.clone(),
+                parser: parser.clone(),
+                encoder: encoder.clone(),
+                formatter: formatter.clone(),
+                shutdown: shutdown.clone(),
+                timeouts,
+                parser_limits,
+                error_handler: error_handler.clone()
+            };
+            let (read_half, write_half) = tokio::io::split(stream);
+            serve_connection(read_half, write_half, context, None)
+        };
+        let accepting = listeners
+            .into_iter()
+            .map(|listener| listener.accept_connections(&config, handle.signal(), serve.clone()));
+        let connections = futures::future::join_all(accepting).await;
+        drain_connections(connections, shutdown_timeout).await;
+        Ok(())
+    }
+}
+
+unsafe impl Send for BetaHttpProtocol {}
+unsafe impl Sync for BetaHttpProtocol {}
+
+pub(crate) struct BetaConnectionContext {
+    pub(crate) connection: Arc<ConnectionInfo>,
+    pub(crate) router: Arc<Router>,
+    pub(crate) parser: Arc<BetaHttpParser>,
+    pub(crate) encoder: Arc<BetaHttpEncoder>,
+    pub(crate) formatter: Arc<Box<dyn HttpResponseFormatter + Send + Sync>>,
+    pub(crate) shutdown: ShutdownSignal,
+    pub(crate) timeouts: Timeouts,
+    pub(crate) parser_limits: ParserLimits,
+    pub(crate) error_handler: ErrorHandler
+}
+
+/// A request that came with `Upgrade: h2c` over HTTP/1.1, which is answered on stream 1 of
+/// the HTTP/2 connection it switched to. Its body must have been read in full already.
+pub(crate) struct UpgradedRequest {
+    pub(crate) settings: Vec<Setting>,
+    pub(crate) request: HttpRequest
+}
+
+/// Serves an HTTP/2 connection, starting from the client's connection preface. Frames are
+/// read by a dedicated task, while this one owns the writing side and all of the state.
+/// Once the server starts shutting down, or the connection has no open streams for longer
+/// than the keep-alive timeout, the client is sent a GOAWAY and the streams that are
+/// already open are served to completion.
+pub(crate) async fn serve_connection<R, W>(mut reader: R, writer: W, context: BetaConnectionContext, upgraded: Option<UpgradedRequest>)
+where
+    R: AsyncRead + Unpin + Send + 'static,
+    W: AsyncWrite + Unpin
+{
+    let timeouts = context.timeouts;
+    match tokio::time::timeout(timeouts.request_head, context.parser.parse_preface(&mut reader)).await {
+        Ok(Ok(_)) => {},
+        Ok(Err(e)) => {
+            eprintln!("Failed to parse connection preface: {:?}", e);
+            return;
+        },
+        Err(_) => return
+    }
+    let writer = WriteTimeout::new(writer, timeouts.response_write);
+
+    let (frames_sender, mut frames) = mpsc::channel(32);
+    let parser = context.parser.clone();
+    let reader_task = tokio::spawn(async move {
+        loop {
+            let frame = parser.parse_frame(&mut reader).await.transpose();
+            let failed = !matches!(frame, Some(Ok(_)));
+            if let Some(frame) = frame {
+                if frames_sender.send(frame).await.is_err() {
+                    return;
+                }
+            }
+            if failed {
+                return;
+            }
+        }
+    });
+
+    let (events_sender, mut events) = mpsc::unbounded_channel();
+    let mut connection = BetaConnection::new(writer, context, events_sender);
+    let max_header_list_size = u32::try_from(connection.context.parser_limits.max_header_bytes).unwrap_or(u32::MAX);
+    let settings = [Setting::MaxConcurrentStreams(MAX_CONCURRENT_STREAMS), Setting::MaxHeaderListSize(max_header_list_size)];
+    connection.context.encoder.encode_settings(&settings, &mut connection.outbox);
+    if let Some(upgraded) = upgraded {
+        if let Err(error_code) = connection.accept_upgrade(upgraded) {
+            connection.context.encoder.encode_goaway(0, error_code, &mut connection.outbox);
+            let _ = connection.flush().await;
+            reader_task.abort();
+            return;
+        }
+    }
+
+    let mut reading = true;
+    let mut shutdown = connection.context.shutdown.clone();
+    let idle = tokio::time::sleep(timeouts.keep_alive);
+    tokio::pin!(idle);
+    loop {
+        if connection.flush().await.is_err() {
+            break;
+        }
+        if (connection.going_away || !reading) && connection.streams.values().all(|stream| stream.local_closed) {
+            break;
+        }
+
+        tokio::select! {
+            frame = frames.recv(), if reading => {
+                idle.as_mut().reset(Instant::now() + timeouts.keep_alive);
+                let result = match frame {
+                    Some(Ok(frame)) => connection.handle_frame(frame),
+                    Some(Err(e)) => Err(parser_error_code(&e)),
+                    None => {
+                        reading = false;
+                        Ok(())
+                    }
+                };
+                if let Err(error_code) = result {
+                    connection.context.encoder.encode_goaway(connection.last_stream_id, error_code, &mut connection.outbox);
+                    let _ = connection.flush().await;
+                    break;
+                }
+            },
+            Some(event) = events.recv() => {
+                idle.as_mut().reset(Instant::now() + timeouts.keep_alive);
+                connection.handle_event(event);
+            },
+            _ = shutdown.triggered(), if !connection.going_away => {
+                connection.context.encoder.encode_goaway(connection.last_stream_id, ErrorCode::NoError, &mut connection.outbox);
+                connection.going_away = true;
+            },
+            _ = &mut idle, if !connection.going_away && connection.streams.is_empty() => {
+                connection.context.encoder.encode_goaway(connection.last_stream_id, ErrorCode::NoError, &mut connection.outbox);
+                connection.going_away = true;
+            }
+        }
+    }
+
+    reader_task.abort();
+    for stream in connection.streams.values() {
+        stream.task.abort();
+    }
+}
+
+fn parser_error_code(error: &HttpParserError) -> ErrorCode {
+    match error {
+        HttpParserError::FrameSize => ErrorCode::FrameSizeError,
+        _ => ErrorCode::ProtocolError
+    }
+}
+
+/// Sent by stream tasks, so that everything that touches the connection happens in order
+/// on the connection task.
+enum StreamEvent {
+    Headers {
+        stream_id: u32,
+        headers: Vec<HeaderField>,
+        end_stream: bool
+    },
+    Data {
+        stream_id: u32,
+        data: Vec<u8>,
+        end_stream: bool,
+        sent: oneshot::Sender<()>
+    },
+    Reset {
+        stream_id: u32,
+        error_code: ErrorCode
+    },
+    Consumed {
+        stream_id: u32,
+        amount: usize
+    }
+}
+
+struct BetaStream {
+    send_window: i64,
+    recv_window: i64,
+    /// Where request body data goes, or `None` once nobody is going to read it
+    body: Option<mpsc::UnboundedSender<StreamBodyEvent>>,
+    outgoing: Option<OutgoingData>,
+    /// The `content-length` of the request, which the DATA it's sent must add up to
+    expected_length: Option<usize>,
+    received_length: usize,
+    local_closed: bool,
+    remote_closed: bool,
+    task: AbortHandle
+}
+
+struct OutgoingData {
+    data: Vec<u8>,
+    offset: usize,
+    end_stream: bool,
+    sent: oneshot::Sender<()>
+}
+
+struct PendingHeaders {
+    stream_id: u32,
+    block: Vec<u8>,
+    end_stream: bool
+}
+
+struct BetaConnection<W> {
+    writer: W,
+    outbox: Vec<u8>,
+    context: Arc<BetaConnectionContext>,
+    events: mpsc::UnboundedSender<StreamEvent>,
+    header_decoder: Decoder,
+    header_encoder: Encoder,
+    streams: HashMap<u32, BetaStream>,
+    last_stream_id: u32,
+    send_window: i64,
+    recv_window: i64,
+    peer_initial_window: i64,
+    peer_max_frame_size: usize,
+    pending_headers: Option<PendingHeaders>,
+    going_away: bool
+}
+
+impl<W> BetaConnection<W> where W: AsyncWrite + Unpin {
+    fn new(writer: W, context: BetaConnectionContext, events: mpsc::UnboundedSender<StreamEvent>) -> Self {
+        let mut header_decoder = Decoder::new();
+        header_decoder.set_header_list_limits(context.parser_limits.max_header_bytes, context.parser_limits.max_headers);
+        BetaConnection {
+            writer,
+            outbox: Vec::new(),
+            context: Arc::new(context),
+            events,
+            header_decoder,
+            header_encoder: Encoder::new(),
+            streams: HashMap::new(),
+            last_stream_id: 0,
+            send_window: DEFAULT_WINDOW_SIZE,
+            recv_window: DEFAULT_WINDOW_SIZE,
+            peer_initial_window: DEFAULT_WINDOW_SIZE,
+            peer_max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            pending_headers: None,
+            going_away: false
+        }
+    }
+
+    async fn flush(&mut self) -> std::io::Result<()> {
+        if self.outbox.is_empty() {
+            return Ok(());
+        }
+        self.writer.write_all(&self.outbox).await?;
+        self.writer.flush().await?;
+        self.outbox.clear();
+        Ok(())
+    }
+
+    /// Handles a frame from the client. An error means the whole connection must be
+    /// terminated with the returned code.
+    fn handle_frame(&mut self, frame: Frame) -> Result<(), ErrorCode> {
+        if let Some(pending) = &self.pending_headers {
+            // A header block must be continued right away, without interleaving other frames
+            match &frame {
+                Frame::Continuation { stream_id, .. } if *stream_id == pending.stream_id => {},
+                _ => return Err(ErrorCode::ProtocolError)
+            }
+        }
+
+        match frame {
+            Frame::Data { stream_id, data, end_stream, flow_controlled } => self.handle_data(stream_id, data, end_stream, flow_controlled),
+            Frame::Headers { stream_id, block, end_stream, end_headers } => {
+                if end_headers {
+                    return self.handle_headers(stream_id, block, end_stream);
+                }
+                self.pending_headers = Some(PendingHeaders { stream_id, block, end_stream });
+                Ok(())
+            },
+            Frame::Continuation { block, end_headers, .. } => {
+                let mut pending = self.pending_headers.take().ok_or(ErrorCode::ProtocolError)?;
+                pending.block.extend_from_slice(&block);
+                if pending.block.len() > MAX_HEADER_BLOCK_SIZE {
+                    return Err(ErrorCode::EnhanceYourCalm);
+                }
+                if end_headers {
+                    return self.handle_headers(pending.stream_id, pending.block, pending.end_stream);
+                }
+                self.pending_headers = Some(pending);
+                Ok(())
+            },
+            Frame::Priority { .. } | Frame::Unknown => Ok(()),
+            Frame::RstStream { stream_id, .. } => {
+                if stream_id > self.last_stream_id {
+                    return Err(ErrorCode::ProtocolError);
+                }
+                if let Some(stream) = self.streams.remove(&stream_id) {
+                    stream.task.abort();
+                    if let Some(body) = stream.body {
+                        let _ = body.send(StreamBodyEvent::Reset);
+                    }
+                }
+                Ok(())
+            },
+            Frame::Settings { ack, settings } => {
+                if ack {
+                    return Ok(());
+                }
+                for setting in settings {
+                    self.apply_setting(setting)?;
+                }
+                self.context.encoder.encode_settings_ack(&mut self.outbox);
+                self.flush_all_streams();
+                Ok(())
+            },
+            Frame::PushPromise { .. } => Err(ErrorCode::ProtocolError),
+            Frame::Ping { ack, payload } => {
+                if !ack {
+                    self.context.encoder.encode_ping_ack(payload, &mut self.outbox);
+                }
+                Ok(())
+            },
+            Frame::GoAway { .. } => {
+                self.going_away = true;
+                Ok(())
+            },
+            Frame::WindowUpdate { stream_id: 0, increment } => {
+                if increment == 0 {
+                    return Err(ErrorCode::ProtocolError);
+                }
+                self.send_window += increment as i64;
+                if self.send_window > MAX_WINDOW_SIZE {
+                    return Err(ErrorCode::FlowControlError);
+                }
+                self.flush_all_streams();
+                Ok(())
+            },
+            Frame::WindowUpdate { stream_id, increment } => {
+                // Streams that were closed may still be updated, but idle ones can't be
+                if stream_id > self.last_stream_id {
+                    return Err(ErrorCode::ProtocolError);
+                }
+                let Some(stream) = self.streams.get_mut(&stream_id) else {
+                    return Ok(());
+                };
+                stream.send_window += increment as i64;
+                let window = stream.send_window;
+                if increment == 0 {
+                    self.reset_stream(stream_id, ErrorCode::ProtocolError);
+                } else if window > MAX_WINDOW_SIZE {
+                    self.reset_stream(stream_id, ErrorCode::FlowControlError);
+                } else {
+                    self.flush_stream(stream_id);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn apply_setting(&mut self, setting: Setting) -> Result<(), ErrorCode> {
+        match setting {
+            Setting::EnablePush(value) if value > 1 => return Err(ErrorCode::ProtocolError),
+            Setting::HeaderTableSize(value) => self.header_encoder.set_max_table_size(value as usize),
+            Setting::InitialWindowSize(value) => {
+                let value = value as i64;
+                if value > MAX_WINDOW_SIZE {
+                    return Err(ErrorCode::FlowControlError);
+                }
+                let delta = value - self.peer_initial_window;
+                self.peer_initial_window = value;
+                for stream in self.streams.values_mut() {
+                    stream.send_window += delta;
+                    if stream.send_window > MAX_WINDOW_SIZE {
+                        return Err(ErrorCode::FlowControlError);
+                    }
+                }
+            },
+            Setting::MaxFrameSize(value) => {
+                if !(DEFAULT_MAX_FRAME_SIZE as u32..=MAX_FRAME_SIZE_LIMIT).contains(&value) {
+                    return Err(ErrorCode::ProtocolError);
+                }
+                self.peer_max_frame_size = value as usize;
+            },
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn handle_data(&mut self, stream_id: u32, data: Vec<u8>, end_stream: bool, flow_controlled: usize) -> Result<(), ErrorCode> {
+        self.recv_window -= flow_controlled as i64;
+        if self.recv_window < 0 {
+            return Err(ErrorCode::FlowControlError);
+        }
+        // The connection window is given back right away, while stream windows only grow
+        // back as handlers consume their bodies, which bounds what's buffered per stream
+        if flow_controlled > 0 {
+            self.recv_window += flow_controlled as i64;
+            self.context.encoder.encode_window_update(0, flow_controlled as u32, &mut self.outbox);
+        }
+
+        if stream_id > self.last_stream_id {
+            return Err(ErrorCode::ProtocolError);
+        }
+        // Frames for streams that were closed or reset may still be in flight, so they're ignored
+        let Some(stream) = self.streams.get_mut(&stream_id) else {
+            return Ok(());
+        };
+        if stream.remote_closed {
+            self.reset_stream(stream_id, ErrorCode::StreamClosed);
+            return Ok(());
+        }
+
+        stream.recv_window -= flow_controlled as i64;
+        if stream.recv_window < 0 {
+            self.reset_stream(stream_id, ErrorCode::FlowControlError);
+            return Ok(());
+        }
+        // A body that doesn't add up to its content-length makes the request malformed
+        stream.received_length += data.len();
+        let mismatched = stream.expected_length.is_some_and(|expected| {
+            stream.received_length > expected || (end_stream && stream.received_length != expected)
+        });
+        if mismatched {
+            self.reset_stream(stream_id, ErrorCode::ProtocolError);
+            return Ok(());
+        }
+
+        // Padding, and data nobody is going to read, count as consumed immediately
+        let mut consumed = flow_controlled - data.len();
+        if !data.is_empty() {
+            let length = data.len();
+            let delivered = stream.body
+                .as_ref()
+                .is_some_and(|body| body.send(StreamBodyEvent::Data(data)).is_ok());
+            if !delivered {
+                consumed += length;
+            }
+        }
+        if end_stream {
+            if let Some(body) = stream.body.take() {
+                let _ = body.send(StreamBodyEvent::End);
+            }
+            stream.remote_closed = true;
+        } else if consumed > 0 {
+            stream.recv_window += consumed as i64;
+            self.context.encoder.encode_window_update(stream_id, consumed as u32, &mut self.outbox);
+        }
+        self.close_if_done(stream_id);
+        Ok(())
+    }
+
+    fn handle_headers(&mut self, stream_id: u32, block: Vec<u8>, end_stream: bool) -> Result<(), ErrorCode> {
+        // The block must be decoded no matter what happens to the stream, otherwise the
+        // dynamic table would fall out of sync with the client's
+        let headers = match self.header_decoder.decode(&block) {
+            Ok(headers) => Some(headers),
+            Err(HpackError::HeaderListTooLarge) => None,
+            Err(_) => return Err(ErrorCode::CompressionError)
+        };
+
+        if let Some(stream) = self.streams.get_mut(&stream_id) {
+            if stream.remote_closed {
+                return Err(ErrorCode::StreamClosed);
+            }
+            if !end_stream {
+                self.reset_stream(stream_id, ErrorCode::ProtocolError);
+                return Ok(());
+            }
+            let complete = stream.expected_length.is_none_or(|expected| expected == stream.received_length);
+            let Some(trailers) = headers.and_then(into_header_map).filter(|_| complete) else {
+                self.reset_stream(stream_id, ErrorCode::ProtocolError);
+                return Ok(());
+            };
+            stream.remote_closed = true;
+            if let Some(body) = stream.body.take() {
+                let _ = body.send(StreamBodyEvent::Trailers(trailers));
+                let _ = body.send(StreamBodyEvent::End);
+            }
+            self.close_if_done(stream_id);
+            return Ok(());
+        }
+
+        if stream_id.is_multiple_of(2) {
+            return Err(ErrorCode::ProtocolError);
+        }
+        if stream_id <= self.last_stream_id {
+            return Ok(());
+        }
+        self.last_stream_id = stream_id;
+        if self.going_away {
+            return Ok(());
+        }
+        if self.streams.len() >= MAX_CONCURRENT_STREAMS as usize {
+            self.context.encoder.encode_rst_stream(stream_id, ErrorCode::RefusedStream, &mut self.outbox);
+            return Ok(());
+        }
+        let Some(headers) = headers else {
+            self.refuse_request(stream_id, HttpParserError::HeadersTooLarge, end_stream);
+            return Ok(());
+        };
+
+        let (body, body_events) = match end_stream {
+            true => (None, None),
+            false => {
+                let (sender, receiver) = mpsc::unbounded_channel();
+                (Some(sender), Some(receiver))
+            }
+        };
+        let events = self.events.clone();
+        let body_reader = LazyBodyReader::from_stream(
+            body_events,
+            self.context.timeouts.request_body,
+            self.context.parser_limits.max_body_size,
+            move |amount| {
+                let _ = events.send(StreamEvent::Consumed { stream_id, amount });
+            }
+        );
+        let request = build_request(headers, body_reader, &self.context.connection)
+            .filter(|request| !end_stream || request.content_length.is_none_or(|length| length == 0));
+        let Some(request) = request else {
+            self.context.encoder.encode_rst_stream(stream_id, ErrorCode::ProtocolError, &mut self.outbox);
+            return Ok(());
+        };
+
+        self.open_stream(stream_id, request, body);
+        Ok(())
+    }
+
+    /// Answers a request over the limits with the response of the error handler, such as
+    /// `431 Request Header Fields Too Large`, without routing it. A client that's still
+    /// sending a body is told to stop once the response is complete.
+    fn refuse_request(&mut self, stream_id: u32, error: HttpParserError, end_stream: bool) {
+        let Some(error) = RequestError::from_parser_error(&error) else {
+            self.context.encoder.encode_rst_stream(stream_id, ErrorCode::ProtocolError, &mut self.outbox);
+            return;
+        };
+        let response = (self.context.error_handler)(&error);
+        let (context, events) = (self.context.clone(), self.events.clone());
+        let task = tokio::spawn(async move { send_response(stream_id, response, false, &context, &events).await });
+        self.insert_stream(stream_id, task.abort_handle(), None, end_stream, None);
+    }
+
+    /// Takes over the request the connection was upgraded with, along with the settings
+    /// from its `HTTP2-Settings` field, which count as acknowledged already.
+    fn accept_upgrade(&mut self, upgraded: UpgradedRequest) -> Result<(), ErrorCode> {
+        for setting in upgraded.settings {
+            self.apply_setting(setting)?;
+        }
+        self.last_stream_id = 1;
+        self.open_stream(1, upgraded.request, None);
+        Ok(())
+    }
+
+    /// Starts responding to a request. Streams without a body sender are half-closed by
+    /// the client already.
+    fn open_stream(&mut self, stream_id: u32, request: HttpRequest, body: Option<mpsc::UnboundedSender<StreamBodyEvent>>) {
+        let expected_length = request.content_length.filter(|_| body.is_some());
+        let task = tokio::spawn(respond(stream_id, request, self.context.clone(), self.events.clone()));
+        let remote_closed = body.is_none();
+        self.insert_stream(stream_id, task.abort_handle(), body, remote_closed, expected_length);
+    }
+
+    fn insert_stream(
+        &mut self,
+        stream_id: u32,
+        task: AbortHandle,
+        body: Option<mpsc::UnboundedSender<StreamBodyEvent>>,
+        remote_closed: bool,
+        expected_length: Option<usize>
+    ) {
+        self.streams.insert(stream_id, BetaStream {
+            send_window: self.peer_initial_window,
+            recv_window: DEFAULT_WINDOW_SIZE,
+            remote_closed,
+            body,
+            outgoing: None,
+            expected_length,
+            received_length: 0,
+            local_closed: false,
+            task
+        });
+    }
+
+    fn handle_event(&mut self, event: StreamEvent) {
+        match event {
+            StreamEvent::Headers { stream_id, headers, end_stream } => {
+                let Some(stream) = self.streams.get_mut(&stream_id) else {
+                    return;
+                };
+                let block = self.header_encoder.encode(&headers);
+                self.context.encoder.encode_headers(stream_id, &block, end_stream, self.peer_max_frame_size, &mut self.outbox);
+                if end_stream {
+                    stream.local_closed = true;
+                    self.close_if_done(stream_id);
+                }
+            },
+            StreamEvent::Data { stream_id, data, end_stream, sent } => {
+                let Some(stream) = self.streams.get_mut(&stream_id) else {
+                    return;
+                };
+                stream.outgoing = Some(OutgoingData { data, offset: 0, end_stream, sent });
+                self.flush_stream(stream_id);
+            },
+            StreamEvent::Reset { stream_id, error_code } => self.reset_stream(stream_id, error_code),
+            StreamEvent::Consumed { stream_id, amount } => {
+                let Some(stream) = self.streams.get_mut(&stream_id) else {
+                    return;
+                };
+                if stream.body.is_some() && amount > 0 {
+                    stream.recv_window += amount as i64;
+                    self.context.encoder.encode_window_update(stream_id, amount as u32, &mut self.outbox);
+                }
+            }
+        }
+    }
+
+    /// Sends as much of the stream's pending data as both flow-control windows allow.
+    fn flush_stream(&mut self, stream_id: u32) {
+        let Some(stream) = self.streams.get_mut(&stream_id) else {
+            return;
+        };
+
+        while let Some(outgoing) = &mut stream.outgoing {
+            let remaining = outgoing.data.len() - outgoing.offset;
+            let window = self.send_window.min(stream.send_window).max(0) as usize;
+            let size = remaining.min(window).min(self.peer_max_frame_size);
+            if size == 0 && remaining > 0 {
+                break;
+            }
+
+            let last = size == remaining;
+            let end_stream = last && outgoing.end_stream;
+            self.context.encoder.encode_data(stream_id, &outgoing.data[outgoing.offset..outgoing.offset + size], end_stream, &mut self.outbox);
+            outgoing.offset += size;
+            self.send_window -= size as i64;
+            stream.send_window -= size as i64;
+
+            if last {
+                let outgoing = stream.outgoing.take().unwrap();
+                let _ = outgoing.sent.send(());
+                stream.local_closed |= end_stream;
+            }
+        }
+        self.close_if_done(stream_id);
+    }
+
+    fn flush_all_streams(&mut self) {
+        let pending: Vec<u32> = self.streams
+            .iter()
+            .filter(|(_, stream)| stream.outgoing.is_some())
+            .map(|(stream_id, _)| *stream_id)
+            .collect();
+        for stream_id in pending {
+            self.flush_stream(stream_id);
+        }
+    }
+
+    fn reset_stream(&mut self, stream_id: u32, error_code: ErrorCode) {
+        self.context.encoder.encode_rst_stream(stream_id, error_code, &mut self.outbox);
+        if let Some(stream) = self.streams.remove(&stream_id) {
+            stream.task.abort();
+            if let Some(body) = stream.body {
+                let _ = body.send(StreamBodyEvent::Reset);
+            }
+        }
+    }
+
+    /// Forgets about the stream once both sides are closed. When the response is complete
+    /// but the client is still sending a request body, the rest of it is discarded and its
+    /// window given back, so that the client can finish sending it.
+    fn close_if_done(&mut self, stream_id: u32) {
+        let Some(stream) = self.streams.get_mut(&stream_id) else {
+            return;
+        };
+        if !stream.local_closed {
+            return;
+        }
+        if stream.remote_closed {
+            self.streams.remove(&stream_id);
+            return;
+        }
+        if stream.body.take().is_some() {
+            let outstanding = DEFAULT_WINDOW_SIZE - stream.recv_window;
+            stream.recv_window = DEFAULT_WINDOW_SIZE;
+            if outstanding > 0 {
+                self.context.encoder.encode_window_update(stream_id, outstanding as u32, &mut self.outbox);
+            }
+        }
+        self.context.encoder.encode_rst_stream(stream_id, ErrorCode::NoError, &mut self.outbox);
+        self.streams.remove(&stream_id);
+    }
+}
+
+/// Turns a decoded header list into a request. Returns `None` for malformed requests,
+/// which are refused with a stream error.
+fn build_request(headers: Vec<HeaderField>, body_reader: LazyBodyReader, connection: &Arc<ConnectionInfo>) -> Option<HttpRequest> {
+    let mut method = None;
+    let mut path = None;
+    let mut scheme = None;
+    let mut authority = None;
+    let mut regular_headers = Vec::with_capacity(headers.len());
+
+    for field in headers {
+        // Pseudo-headers must all come before regular fields, and no name may be uppercase
+        if field.name.starts_with(':') && !regular_headers.is_empty() {
+            return None;
+        }
+        if field.name.bytes().any(|byte| byte.is_ascii_uppercase()) {
+            return None;
+        }
+        match field.header() {
+            HttpHeader::PseudoMethod => method = HttpMethod::from_str(&field.value),
+            HttpHeader::PseudoPath => path = Some(field.value),
+            HttpHeader::PseudoScheme => scheme = Some(HttpScheme::from_str(&field.value)),
+            HttpHeader::PseudoAuthority => authority = Some(field.value),
+            _ if field.name.starts_with(':') => return None,
+            _ => regular_headers.push(field)
+        }
+    }
+
+    let mut headers = into_header_map(regular_headers)?;
+    if let Some(authority) = authority {
+        if !headers.contains_key(&HttpHeader::Host) {
+            headers.try_insert(HttpHeader::Host, authority.clone()).ok()?;
+        }
+        headers.try_insert(HttpHeader::PseudoAuthority, authority).ok()?;
+    }
+    let content_length = content_length(&headers).ok()?;
+
+    Some(HttpRequest {
+        protocol: crate::request::HttpProtocol::HTTP2,
+        scheme: scheme?,
+        path: path.filter(|path| !path.is_empty())?,
+        method: method?,
+        headers,
+        flow: OnceCell::new(),
+        peer_addr: connection.peer_addr,
+        content_length,
+        body_reader,
+        path_values: OnceCell::new(),
+        version: HttpVersion::Http2,
+        keep_alive: true,
+        connection: connection.clone()
+    })
+}
+
+/// Keeps every value of repeated fields, except for cookies, which HTTP/2 clients split into
+/// several fields and are joined back into one. Returns `None` if a name or value is malformed.
+fn into_header_map(headers: Vec<HeaderField>) -> Option<HeaderMap> {
+    let mut map = HeaderMap::with_capacity(headers.len());
+    let mut cookies: Option<String> = None;
+    for field in headers {
+        match field.into() {
+            (HttpHeader::Cookie, value) => match &mut cookies {
+                Some(cookies) => {
+                    cookies.push_str("; ");
+                    cookies.push_str(&value);
+                },
+                None => cookies = Some(value)
+            },
+            (header, value) => map.try_append(header, value).ok()?
+        }
+    }
+    if let Some(cookies) = cookies {
+        map.try_append(HttpHeader::Cookie, cookies).ok()?;
+    }
+    Some(map)
+}
+
+/// Routes a request and answers it.
+async fn respond(
+    stream_id: u32,
+    request: HttpRequest,
+    context: Arc<BetaConnectionContext>,
+    events: mpsc::UnboundedSender<StreamEvent>
+) {
+    let head_only = request.method == HttpMethod::HEAD;
+    let response = route_request(&context.router, request).await;
+    send_response(stream_id, response, head_only, &context, &events).await;
+}
+
+/// Streams a response back through the connection task, waiting for every chunk to be sent
+/// before producing the next one.
+async fn send_response(
+    stream_id: u32,
+    response: HttpResponse,
+    head_only: bool,
+    context: &BetaConnectionContext,
+    events: &mpsc::UnboundedSender<StreamEvent>
+) {
+    let mut response = context.formatter.format(response);
+    let mut headers = vec![HeaderField::from((HttpHeader::PseudoStatus, response.status_code.code().to_string()))];
+    headers.extend(into_field_list(response.headers));
+    // Responses to HEAD requests keep the headers of the body they leave out
+    let end_stream = head_only || !response.status_code.allows_body() || response.body.is_empty();
+    if events.send(StreamEvent::Headers { stream_id, headers, end_stream }).is_err() || end_stream {
+        return;
+    }
+
+    while let Some(chunk) = response.body.next_chunk().await {
+        let data = match chunk {
+            Ok(data) => data,
+            Err(_) => {
+                let _ = events.send(StreamEvent::Reset { stream_id, error_code: ErrorCode::InternalError });
+                return;
+            }
+        };
+        let (sent, on_sent) = oneshot::channel();
+        if events.send(StreamEvent::Data { stream_id, data, end_stream: false, sent }).is_err() || on_sent.await.is_err() {
+            return;
+        }
+    }
+
+    let _ = match response.body.trailers().await {
+        Some(trailers) => events.send(StreamEvent::Headers { stream_id, headers: into_field_list(trailers), end_stream: true }),
+        None => {
+            let (sent, _) = oneshot::channel();
+            events.send(StreamEvent::Data { stream_id, data: Vec::new(), end_stream: true, sent })
+        }
+    };
+}
+
+/// Leaves out the connection-specific fields, which are forbidden in HTTP/2.
+fn into_field_list(headers: HeaderMap) -> Vec<HeaderField> {
+    headers
+        .into_iter()
+        .filter(|(header, _)| !matches!(header, HttpHeader::Connection | HttpHeader::TransferEncoding | HttpHeader::Upgrade))
+        .filter(|(header, _)| !matches!(header, HttpHeader::Custom(name) if name.eq_ignore_ascii_case("keep-alive") || name.eq_ignore_ascii_case("proxy-connection")))
+        .map(HeaderField::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::alpha::AlphaHttpEncoder;
+    use crate::encoder::format::DefaultHttpResponseFormatter;
+    use crate::handle::ShutdownSignal;
+    use crate::hpack::{Decoder, Encoder, HeaderField};
+    use crate::parser::alpha::AlphaHttpParser;
+    use crate::protocol::alpha::AlphaConnectionContext;
+    use crate::protocol::stream::ConnectionStream;
+    use crate::request::PeerAddr;
+    use crate::response::into::IntoResponse;
+    use crate::router::func::function_handler;
+    use crate::router::{HttpRouter, Router};
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+
+    const DATA: u8 = 0x0;
+    const HEADERS: u8 = 0x1;
+    const RST_STREAM: u8 = 0x3;
+    const GOAWAY: u8 = 0x7;
+    const WINDOW_UPDATE: u8 = 0x8;
+    const END_STREAM: u8 = 0x1;
+    const END_HEADERS: u8 = 0x4;
+    const PROTOCOL_ERROR: [u8; 4] = [0, 0, 0, 1];
+    const SETTINGS: u8 = 0x4;
+    const END_STREAM_AND_HEADERS: u8 = 0x5;
+
+    fn frame(kind: u8, flags: u8, stream_id: u32, payload: &[u8]) -> Vec<u8> {
+        let mut frame = (payload.len() as u32).to_be_bytes()[1..].to_vec();
+        frame.extend_from_slice(&[kind, flags]);
+        frame.extend_from_slice(&stream_id.to_be_bytes());
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    async fn read_frame(client: &mut DuplexStream) -> (u8, u32, Vec<u8>) {
+        let mut header = [0; 9];
+        client.read_exact(&mut header).await.unwrap();
+        let length = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+        let stream_id = u32::from_be_bytes([header[5], header[6], header[7], header[8]]) & 0x7fff_ffff;
+        let mut payload = vec![0; length];
+        client.read_exact(&mut payload).await.unwrap();
+        (header[3], stream_id, payload)
+    }
+
+    async fn connect() -> DuplexStream {
+        connect_with(default_error_handler()).await
+    }
+
+    async fn connect_with(error_handler: ErrorHandler) -> DuplexStream {
+        let router = Arc::new(Router::new()
+            .add_handler("/", function_handler(|_| async { "root" }))
+            .add_handler("/echo", function_handler(|mut request| async move {
+                String::from_utf8_lossy(request.read_body().await.unwrap()).into_owned()
+            })));
+        let (mut client, server) = tokio::io::duplex(1 << 20);
+        let stream = ConnectionStream::Transport(Box::new(server));
+        let context = AlphaConnectionContext {
+            connection: Arc::new(stream.info(PeerAddr::Unknown, None)),
+            router,
+            parser: Arc::new(AlphaHttpParser::new()),
+            encoder: Arc::new(AlphaHttpEncoder::new()),
+            formatter: Arc::new(Box::new(DefaultHttpResponseFormatter::new())),
+            shutdown: ShutdownSignal::never(),
+            timeouts: Timeouts::default(),
+            error_handler
+        };
+        tokio::spawn(crate::protocol::alpha::serve_stream(stream, context));
+        client.write_all(crate::parser::beta::CONNECTION_PREFACE).await.unwrap();
+        client.write_all(&frame(SETTINGS, 0, 0, &[])).await.unwrap();
+        client
+    }
+
+    /// The status of the first response the server sends on the stream.
+    async fn response_status(client: &mut DuplexStream, decoder: &mut Decoder, stream_id: u32) -> String {
+        loop {
+            let (kind, id, payload) = read_frame(client).await;
+            if kind == HEADERS && id == stream_id {
+                let fields = decoder.decode(&payload).unwrap();
+                return fields.into_iter().find(|field| field.name == ":status").unwrap().value;
+            }
+        }
+    }
+
+    fn request(path: &str) -> Vec<HeaderField> {
+        vec![
+            HeaderField::new(":method", "GET"),
+            HeaderField::new(":scheme", "http"),
+            HeaderField::new(":path", path),
+            HeaderField::new(":authority", "localhost")
+        ]
+    }
+
+    #[tokio::test]
+    async fn header_list_size_is_advertised() {
+        let mut client = connect().await;
+        let (kind, _, payload) = read_frame(&mut client).await;
+        assert_eq!(kind, SETTINGS);
+        let settings: Vec<(u16, u32)> = payload
+            .chunks(6)
+            .map(|pair| (u16::from_be_bytes([pair[0], pair[1]]), u32::from_be_bytes([pair[2], pair[3], pair[4], pair[5]])))
+            .collect();
+        assert!(settings.contains(&(0x6, 64 * 1024)), "{settings:?}");
+    }
+
+    #[tokio::test]
+    async fn repeated_references_to_a_large_entry_are_refused() {
+        let mut client = connect().await;
+        let mut encoder = Encoder::new();
+        let mut fields = request("/");
+        fields.push(HeaderField::new("x-large", "a".repeat(4000)));
+        let mut block = encoder.encode(&fields);
+        // Each byte references the large entry, the most recent one in the dynamic table, which
+        // goes over the size but not over the number of fields
+        block.extend(std::iter::repeat_n(0x80 | 62, 20));
+        client.write_all(&frame(HEADERS, END_STREAM_AND_HEADERS, 1, &block)).await.unwrap();
+        client.write_all(&frame(HEADERS, END_STREAM_AND_HEADERS, 3, &encoder.encode(&request("/")))).await.unwrap();
+
+        let mut decoder = Decoder::new();
+        assert_eq!(response_status(&mut client, &mut decoder, 1).await, "431");
+        // The dynamic table is still in sync, so the connection goes on
+        assert_eq!(response_status(&mut client, &mut decoder, 3).await, "200");
+    }
+
+    #[tokio::test]
+    async fn oversized_header_list_is_answered_by_the_error_handler() {
+        let mut client = connect_with(Arc::new(|error: &RequestError| (error.status(), "custom").into_response())).await;
+        let mut fields = request("/");
+        fields.push(HeaderField::new("x-large", "a".repeat(4000)));
+        let mut block = Encoder::new().encode(&fields);
+        block.extend(std::iter::repeat_n(0x80 | 62, 20));
+        client.write_all(&frame(HEADERS, END_STREAM_AND_HEADERS, 1, &block)).await.unwrap();
+
+        assert_eq!(response_status(&mut client, &mut Decoder::new(), 1).await, "431");
+        assert_eq!(next_frame_of(&mut client, DATA).await, (1, b"custom".to_vec()));
+    }
+
+    /// The first frame of the given kind the server sends, skipping the others.
+    async fn next_frame_of(client: &mut DuplexStream, kind: u8) -> (u32, Vec<u8>) {
+        loop {
+            let (frame_kind, stream_id, payload) = read_frame(client).await;
+            if frame_kind == kind {
+                return (stream_id, payload);
+            }
+        }
+    }
+
+    fn post(content_length: usize) -> Vec<HeaderField> {
+        vec![
+            HeaderField::new(":method", "POST"),
+            HeaderField::new(":scheme", "http"),
+            HeaderField::new(":path", "/echo"),
+            HeaderField::new(":authority", "localhost"),
+            HeaderField::new("content-length", content_length.to_string())
+        ]
+    }
+
+    #[tokio::test]
+    async fn body_that_differs_from_its_content_length_resets_the_stream() {
+        let cases: [(usize, &[&[u8]], bool); 4] = [
+            (5, &[b"hel"], true),
+            (2, &[b"hel", b"lo"], false),
+            (5, &[b"hel", b"loo"], false),
+            (5, &[], true)
+        ];
+        for (content_length, chunks, ended_by_data) in cases {
+            let mut client = connect().await;
+            let flags = if chunks.is_empty() { END_STREAM_AND_HEADERS } else { END_HEADERS };
+            client.write_all(&frame(HEADERS, flags, 1, &Encoder::new().encode(&post(content_length)))).await.unwrap();
+            for (index, chunk) in chunks.iter().enumerate() {
+                let last = ended_by_data && index == chunks.len() - 1;
+                client.write_all(&frame(DATA, if last { END_STREAM } else { 0 }, 1, chunk)).await.unwrap();
+            }
+            assert_eq!(next_frame_of(&mut client, RST_STREAM).await, (1, PROTOCOL_ERROR.to_vec()), "{content_length} {chunks:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn body_that_matches_its_content_length_is_served() {
+        let mut client = connect().await;
+        client.write_all(&frame(HEADERS, END_HEADERS, 1, &Encoder::new().encode(&post(5)))).await.unwrap();
+        client.write_all(&frame(DATA, 0, 1, b"hel")).await.unwrap();
+        client.write_all(&frame(DATA, END_STREAM, 1, b"lo")).await.unwrap();
+        assert_eq!(response_status(&mut client, &mut Decoder::new(), 1).await, "200");
+        assert_eq!(next_frame_of(&mut client, DATA).await, (1, b"hello".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn window_update_for_an_idle_stream_is_a_connection_error() {
+        let mut client = connect().await;
+        let mut encoder = Encoder::new();
+        let mut decoder = Decoder::new();
+        client.write_all(&frame(HEADERS, END_STREAM_AND_HEADERS, 1, &encoder.encode(&request("/")))).await.unwrap();
+        assert_eq!(response_status(&mut client, &mut decoder, 1).await, "200");
+        next_frame_of(&mut client, DATA).await;
+
+        // Stream 1 is closed, which is fine, while stream 3 was never opened
+        client.write_all(&frame(WINDOW_UPDATE, 0, 1, &1u32.to_be_bytes())).await.unwrap();
+        client.write_all(&frame(WINDOW_UPDATE, 0, 3, &1u32.to_be_bytes())).await.unwrap();
+        let (_, payload) = next_frame_of(&mut client, GOAWAY).await;
+        assert_eq!(payload, [[0, 0, 0, 1], PROTOCOL_ERROR].concat());
+    }
+}
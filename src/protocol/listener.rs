@@ -0,0 +1,369 @@
+use crate::handle::ShutdownSignal;
+use crate::listener::{ListenAddress, ListenerConfig, ListenerInfo};
+use crate::protocol::stream::{ConnectionAcceptor, ConnectionStream};
+use crate::protocol::proxy::read_header;
+use crate::protocol::{ConnectionInfo, HttpProtocolError};
+use crate::proxy::ProxyHeader;
+use crate::limits::{ConnectionLimiter, LimitBehavior};
+use crate::request::PeerAddr;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio::task::JoinSet;
+#[cfg(unix)]
+use std::path::Path;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+
+/// A listener a protocol accepts connections from, along with what requests get to know
+/// about it.
+pub(crate) struct BoundListener {
+    listener: Listener,
+    pub(crate) info: ListenerInfo,
+    /// Takes the place of the protocol's acceptor when the listener has its own TLS config
+    pub(crate) acceptor: Option<ConnectionAcceptor>,
+    proxy_protocol: bool
+}
+
+impl BoundListener {
+    pub(crate) async fn bind_tcp(addr: &str) -> Result<Self, HttpProtocolError> {
+        Ok(BoundListener::unnamed(Listener::bind_tcp(addr).await?))
+    }
+
+    /// Binds the listener, offering the given ALPN protocols if it terminates TLS.
+    pub(crate) async fn bind(
+        config: ListenerConfig,
+        #[cfg(feature = "tls")] alpn_protocols: &[&[u8]]
+    ) -> crate::Result<Self> {
+        let listener = match config.address {
+            ListenAddress::Tcp(addr) => Listener::bind_tcp(&addr.to_string()).await?,
+            #[cfg(unix)]
+            ListenAddress::Unix(path, mode) => Listener::bind_unix(&path, mode)?,
+            #[cfg(unix)]
+            ListenAddress::Fd(fd) => Listener::from_fd(fd)?
+        };
+        let mut bound = BoundListener::unnamed(listener);
+        bound.proxy_protocol = config.proxy_protocol;
+        if let Some(name) = config.name {
+            bound.info = ListenerInfo::new(name.into(), bound.info.local_addr());
+        }
+        #[cfg(feature = "tls")]
+        if let Some(tls) = config.tls {
+            bound.acceptor = Some(ConnectionAcceptor { tls: Some(tls.acceptor(alpn_protocols)?) });
+        }
+        Ok(bound)
+    }
+
+    fn unnamed(listener: Listener) -> Self {
+        let (name, local_addr) = match &listener {
+            Listener::Tcp(listener) => {
+                let local_addr = listener.local_addr().ok();
+                let name = local_addr.map_or_else(|| "tcp".to_string(), |addr| addr.to_string());
+                (name, local_addr)
+            },
+            #[cfg(unix)]
+            Listener::Unix(listener) => {
+                let name = listener
+                    .local_addr()
+                    .ok()
+                    .and_then(|addr| addr.as_pathname().map(|path| path.display().to_string()))
+                    .unwrap_or_else(|| "unix".to_string());
+                (name, None)
+            }
+        };
+        BoundListener {
+            listener,
+            info: ListenerInfo::new(name.into(), local_addr),
+            acceptor: None,
+            proxy_protocol: false
+        }
+    }
+
+    /// Accepts connections until shutdown is triggered, serving each of them on its own task
+    /// once its PROXY protocol header is read and TLS is set up, when the listener has them.
+    /// Connections that take longer than the handshake timeout to get there are dropped, and
+    /// so are the ones over the connection limits, which are checked before the handshake
+    /// unless the client is only known from its PROXY header. Returns the tasks of the
+    /// connections that are still open, while the listener itself is closed.
+    pub(crate) async fn accept_connections<F, Fut>(
+        self,
+        config: &AcceptConfig,
+        mut shutdown: ShutdownSignal,
+        serve: F
+    ) -> JoinSet<()>
+    where
+        F: Fn(ConnectionStream, Arc<ConnectionInfo>) -> Fut + Clone + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static
+    {
+        let acceptor = self.acceptor.clone().unwrap_or_else(|| config.acceptor.clone());
+        let mut connections = JoinSet::new();
+        let mut backoff = MIN_ACCEPT_BACKOFF;
+        loop {
+            // While paused at the limit, new connections wait in the listen backlog
+            let reserved = tokio::select! {
+                _ = shutdown.triggered() => return connections,
+                // Finished connections are reaped as they go, so that they don't pile up
+                Some(_) = connections.join_next(), if !connections.is_empty() => continue,
+                reserved = config.limiter.reserve() => reserved
+            };
+            let accepted = tokio::select! {
+                _ = shutdown.triggered() => return connections,
+                Some(_) = connections.join_next(), if !connections.is_empty() => continue,
+                accepted = self.listener.accept() => accepted
+            };
+            let (mut stream, addr) = match accepted {
+                Ok(connection) => connection,
+                Err(_e) => {
+                    // Errors such as running out of file descriptors last until some connection
+                    // closes, so accepting again right away would only spin
+                    #[cfg(feature = "logging")]
+                    tracing::error!("Failed to accept connection, retrying in {:?}: {}", backoff, _e);
+                    tokio::select! {
+                        _ = shutdown.triggered() => return connections,
+                        _ = tokio::time::sleep(backoff) => {}
+                    }
+                    backoff = (backoff * 2).min(MAX_ACCEPT_BACKOFF);
+                    continue;
+                }
+            };
+            backoff = MIN_ACCEPT_BACKOFF;
+            let acceptor = acceptor.clone();
+            let info = self.info.clone();
+            let serve = serve.clone();
+            let proxy_protocol = self.proxy_protocol;
+            let limiter = config.limiter.clone();
+            let rejection = config.rejection;
+            let handshake_timeout = config.handshake_timeout;
+
+            connections.spawn(async move {
+                // Without a PROXY header the client is known already, so connections over the
+                // limits are turned away before a TLS handshake is spent on them. They can't
+                // be answered over TLS then, so they're just closed.
+                let (reserved, permit) = match proxy_protocol {
+                    true => (reserved, None),
+                    false => match limiter.admit(addr.ip().map(|addr| addr.ip()), reserved) {
+                        Some(permit) => (None, Some(permit)),
+                        None => {
+                            let rejection = rejection.filter(|_| !acceptor.is_tls());
+                            turn_away(&mut stream, rejection, &limiter).await;
+                            return;
+                        }
+                    }
+                };
+                let handshake = async {
+                    // The PROXY header comes before anything else, the TLS handshake included
+                    let proxy = match proxy_protocol {
+                        true => Some(read_header(&mut stream).await?),
+                        false => None
+                    };
+                    Ok::<_, std::io::Error>((acceptor.accept(stream).await?, proxy))
+                };
+                let (mut stream, proxy) = match tokio::time::timeout(handshake_timeout, handshake).await {
+                    Ok(Ok(accepted)) => accepted,
+                    Ok(Err(e)) => {
+                        eprintln!("Failed to accept connection: {}", e);
+                        return;
+                    },
+                    Err(_) => {
+                        eprintln!("Failed to accept connection: the handshake timed out");
+                        return;
+                    }
+                };
+                let mut connection = stream.info(addr, Some(info));
+                if let Some(source) = proxy.as_ref().and_then(ProxyHeader::source) {
+                    connection.peer_addr = PeerAddr::Ip(source);
+                }
+                connection.proxy = proxy;
+
+                let permit = permit.or_else(|| limiter.admit(connection.peer_addr.ip().map(|addr| addr.ip()), reserved));
+                let Some(_permit) = permit else {
+                    turn_away(&mut stream, rejection, &limiter).await;
+                    return;
+                };
+                serve(stream, Arc::new(connection)).await;
+            });
+        }
+    }
+}
+
+/// Closes a connection over the limits, sending it the rejection first when rejecting them,
+/// unless the client negotiated HTTP/2 and wouldn't understand it.
+async fn turn_away(stream: &mut ConnectionStream, rejection: Option<&'static [u8]>, limiter: &ConnectionLimiter) {
+    let rejection = rejection.filter(|_| limiter.behavior() == LimitBehavior::Reject);
+    if let Some(response) = rejection.filter(|_| stream.alpn_protocol() != Some(ALPN_H2)) {
+        let _ = stream.write_all(response).await;
+        let _ = stream.shutdown().await;
+    }
+}
+
+/// What the listeners of a protocol share while accepting connections.
+pub(crate) struct AcceptConfig {
+    /// Used by listeners without a TLS config of their own
+    pub(crate) acceptor: ConnectionAcceptor,
+    pub(crate) handshake_timeout: Duration,
+    pub(crate) limiter: Arc<ConnectionLimiter>,
+    /// What connections over a limit are sent before being closed, when rejecting them
+    pub(crate) rejection: Option<&'static [u8]>
+}
+
+const ALPN_H2: &[u8] = b"h2";
+/// How long accepting waits after an error, doubling with every error in a row
+const MIN_ACCEPT_BACKOFF: Duration = Duration::from_millis(5);
+const MAX_ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Waits for the connections left open at shutdown to finish, for up to `timeout`. The ones
+/// that are still open by then are aborted.
+pub(crate) async fn drain_connections(mut connections: Vec<JoinSet<()>>, timeout: Duration) {
+    let drained = async {
+        for connections in &mut connections {
+            while connections.join_next().await.is_some() {}
+        }
+    };
+    if tokio::time::timeout(timeout, drained).await.is_err() {
+        let remaining: usize = connections.iter().map(JoinSet::len).sum();
+        eprintln!("Aborting {} connections that didn't finish in time", remaining);
+    }
+}
+
+/// Reads an integer option of the socket at the `SOL_SOCKET` level.
+#[cfg(unix)]
+fn socket_option(socket: &impl std::os::fd::AsRawFd, option: libc::c_int) -> std::io::Result<libc::c_int> {
+    let mut value: libc::c_int = 0;
+    let mut length = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    // SAFETY: the descriptor stays open while borrowed, and both pointers are valid for the
+    // size given
+    let result = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            option,
+            (&mut value as *mut libc::c_int).cast(),
+            &mut length
+        )
+    };
+    if result == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(value)
+}
+
+/// A bound socket that connections are accepted from.
+enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener)
+}
+
+impl Listener {
+    async fn bind_tcp(addr: &str) -> Result<Self, HttpProtocolError> {
+        let listener = TcpListener::bind(addr).await.map_err(|_| HttpProtocolError::PortAlreadyInUse)?;
+        Ok(Listener::Tcp(listener))
+    }
+
+    /// Binds a Unix socket at the given path, replacing a socket file left behind by a server
+    /// that is no longer running. The file's permission bits are set to `mode` when given.
+    #[cfg(unix)]
+    fn bind_unix(path: &Path, mode: Option<u32>) -> Result<Self, HttpProtocolError> {
+        use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+        if let Ok(metadata) = std::fs::symlink_metadata(path) {
+            if !metadata.file_type().is_socket() {
+                return Err(HttpProtocolError::NotASocket(path.to_path_buf()));
+            }
+            match std::os::unix::net::UnixStream::connect(path) {
+                Ok(_) => return Err(HttpProtocolError::SocketInUse(path.to_path_buf())),
+                Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused => {
+                    std::fs::remove_file(path).map_err(|e| HttpProtocolError::UnixSocketBind(path.to_path_buf(), e))?;
+                },
+                Err(e) => return Err(HttpProtocolError::UnixSocketBind(path.to_path_buf(), e))
+            }
+        }
+
+        let listener = UnixListener::bind(path).map_err(|e| HttpProtocolError::UnixSocketBind(path.to_path_buf(), e))?;
+        if let Some(mode) = mode {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+                .map_err(|e| HttpProtocolError::UnixSocketBind(path.to_path_buf(), e))?;
+        }
+        Ok(Listener::Unix(listener))
+    }
+
+    /// Adopts a socket that is already listening, telling TCP and Unix sockets apart by the
+    /// address they're bound to. Datagram sockets and ones that were never told to listen are
+    /// refused here, since accepting from them would only fail later on.
+    #[cfg(unix)]
+    fn from_fd(fd: std::os::fd::OwnedFd) -> Result<Self, HttpProtocolError> {
+        use std::os::fd::{AsRawFd, OwnedFd};
+        use std::os::unix::fs::FileTypeExt;
+
+        let raw_fd = fd.as_raw_fd();
+        let adopt = |e| HttpProtocolError::InheritedSocket(raw_fd, e);
+        // Duplicating the descriptor marks it close-on-exec, which inherited ones often aren't
+        let fd = fd.try_clone().map_err(adopt)?;
+        let file = std::fs::File::from(fd);
+        if !file.metadata().map_err(adopt)?.file_type().is_socket() {
+            return Err(HttpProtocolError::NotASocketFd(raw_fd));
+        }
+        if socket_option(&file, libc::SO_TYPE).map_err(adopt)? != libc::SOCK_STREAM {
+            return Err(HttpProtocolError::NotAStreamSocket(raw_fd));
+        }
+        if socket_option(&file, libc::SO_ACCEPTCONN).map_err(adopt)? == 0 {
+            return Err(HttpProtocolError::NotListening(raw_fd));
+        }
+
+        let listener = std::net::TcpListener::from(OwnedFd::from(file));
+        if listener.local_addr().is_ok() {
+            listener.set_nonblocking(true).map_err(adopt)?;
+            return Ok(Listener::Tcp(TcpListener::from_std(listener).map_err(adopt)?));
+        }
+        let listener = std::os::unix::net::UnixListener::from(OwnedFd::from(listener));
+        listener.set_nonblocking(true).map_err(adopt)?;
+        Ok(Listener::Unix(UnixListener::from_std(listener).map_err(adopt)?))
+    }
+
+    async fn accept(&self) -> std::io::Result<(ConnectionStream, PeerAddr)> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, addr) = listener.accept().await?;
+                if let Err(_err) = stream.set_nodelay(true) {
+                    #[cfg(feature = "logging")]
+                    tracing::warn!("Failed to set TCP_NODELAY: {}", _err);
+                }
+                Ok((ConnectionStream::Plain(stream), PeerAddr::Ip(addr)))
+            },
+            #[cfg(unix)]
+            Listener::Unix(listener) => {
+                let (stream, _) = listener.accept().await?;
+                Ok((ConnectionStream::Unix(stream), PeerAddr::Unix))
+            }
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::fd::OwnedFd;
+
+    #[tokio::test]
+    async fn inherited_datagram_socket_is_refused() {
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let adopted = Listener::from_fd(OwnedFd::from(socket));
+        assert!(matches!(adopted, Err(HttpProtocolError::NotAStreamSocket(_))));
+    }
+
+    #[tokio::test]
+    async fn inherited_socket_that_is_not_listening_is_refused() {
+        let (socket, _peer) = std::os::unix::net::UnixStream::pair().unwrap();
+        let adopted = Listener::from_fd(OwnedFd::from(socket));
+        assert!(matches!(adopted, Err(HttpProtocolError::NotListening(_))));
+    }
+
+    #[tokio::test]
+    async fn inherited_listening_socket_is_adopted() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let adopted = Listener::from_fd(OwnedFd::from(listener));
+        assert!(matches!(adopted, Ok(Listener::Tcp(_))));
+    }
+}
@@ -1,16 +1,52 @@
 pub mod alpha;
+pub mod beta;
+pub(crate) mod stream;
+pub(crate) mod listener;
+pub(crate) mod proxy;
 
 use async_trait::async_trait;
+use std::time::Duration;
 use thiserror::Error;
-use crate::router::Router;
+use crate::handle::ServerHandle;
+use crate::listener::{ListenerConfig, ListenerInfo};
+use crate::headers;
+use crate::request::{HttpRequest, HttpScheme, PeerAddr};
+use crate::response::body::HttpBody;
+use crate::response::{HttpResponse, HttpStatusCode};
+use crate::router::{HttpRouter, Router};
+use futures::FutureExt;
+use std::panic::AssertUnwindSafe;
+use crate::timeouts::Timeouts;
+use crate::limits::{ConnectionLimits, ParserLimits};
+use crate::response::error::ErrorHandler;
 
 #[async_trait]
-pub trait HttpProtocol {
+pub trait HttpProtocol: Send + Sync {
     fn set_router(&mut self, router: Router);
 
+    fn set_timeouts(&mut self, timeouts: Timeouts);
+
+    fn set_connection_limits(&mut self, limits: ConnectionLimits);
+
+    fn set_parser_limits(&mut self, limits: ParserLimits);
+
+    /// Customizes the responses to requests that are refused before reaching the router.
+    fn set_error_handler(&mut self, handler: ErrorHandler);
+
+    #[cfg(feature = "tls")]
+    fn set_tls(&mut self, config: crate::tls::TlsConfig) -> crate::Result<()>;
+
     async fn connect(&mut self, addr: &str) -> crate::Result<()>;
 
-    async fn listen(&'static self) -> crate::Result<()>;
+    /// Binds one more listener, which is accepted from alongside the others.
+    async fn add_listener(&mut self, config: ListenerConfig) -> crate::Result<()>;
+
+    /// The listeners bound so far.
+    fn listeners(&self) -> Vec<ListenerInfo>;
+
+    /// Accepts connections until the handle is told to shut down, then gives the open ones
+    /// up to `shutdown_timeout` to finish before returning.
+    async fn listen(self: Box<Self>, handle: ServerHandle, shutdown_timeout: Duration) -> crate::Result<()>;
 }
 
 #[derive(Debug, Error)]
@@ -18,6 +54,61 @@ pub enum HttpProtocolError {
     #[error("The address could not be bound. Port already in use?")]
     PortAlreadyInUse,
     #[error("You must bind the socket before listening")]
-    UnboundSocket
+    UnboundSocket,
+    #[error("You must set a router before listening")]
+    UnsetRouter,
+    #[error("Another server is already listening on {0}")]
+    SocketInUse(std::path::PathBuf),
+    #[error("{0} already exists and is not a socket")]
+    NotASocket(std::path::PathBuf),
+    #[error("Failed to bind a Unix socket at {0}: {1}")]
+    UnixSocketBind(std::path::PathBuf, std::io::Error),
+    #[error("File descriptor {0} is not a socket")]
+    NotASocketFd(i32),
+    #[error("The socket at file descriptor {0} is not a stream socket")]
+    NotAStreamSocket(i32),
+    #[error("The socket at file descriptor {0} is not listening")]
+    NotListening(i32),
+    #[error("Failed to adopt the socket at file descriptor {0}: {1}")]
+    InheritedSocket(i32, std::io::Error)
 }
 
+/// Routes a request, answering with `404 Not Found` when no route matches it and with
+/// `500 Internal Server Error` when its handler panics.
+pub(crate) async fn route_request(router: &Router, request: HttpRequest) -> HttpResponse {
+    let routed_response = AssertUnwindSafe(router.route(request)).catch_unwind().await;
+    match routed_response {
+        Ok(response) => response.unwrap_or_else(|| HttpResponse {
+            status_code: HttpStatusCode::NotFound,
+            headers: headers! {
+                ContentType: "text/plain"
+            },
+            body: HttpBody::empty()
+        }),
+        Err(e) => {
+            eprintln!("Failed to route request: {:?}", e);
+            HttpResponse {
+                status_code: HttpStatusCode::InternalServerError,
+                headers: headers! {
+                    ContentType: "text/plain"
+                },
+                body: HttpBody::empty()
+            }
+        }
+    }
+}
+
+/// What's known about a connection before any request is read from it.
+#[derive(Debug, Clone)]
+pub(crate) struct ConnectionInfo {
+    pub(crate) peer_addr: PeerAddr,
+    pub(crate) scheme: HttpScheme,
+    pub(crate) listener: Option<ListenerInfo>,
+    /// The PROXY protocol header sent ahead of the connection, on listeners that expect one
+    pub(crate) proxy: Option<crate::proxy::ProxyHeader>,
+    #[cfg(unix)]
+    pub(crate) peer_credentials: Option<crate::extractor::peer::PeerCredentials>,
+    /// The verified client certificate, if the client presented one during the handshake
+    #[cfg(feature = "tls")]
+    pub(crate) peer_certificate: Option<crate::tls::PeerCertificate>
+}
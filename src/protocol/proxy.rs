@@ -0,0 +1,253 @@
+use crate::proxy::{ProxyHeader, ProxyTlv};
+use std::io::{Error, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+/// The longest a v1 header can be, line ending included
+const V1_MAX_LENGTH: usize = 107;
+
+/// Reads the PROXY protocol header at the start of a connection, in either version. Nothing
+/// past the header is read, so the stream can be handed to the HTTP parser as is.
+pub(crate) async fn read_header<S: AsyncRead + Unpin>(stream: &mut S) -> std::io::Result<ProxyHeader> {
+    // Both versions can be told apart from the first 12 bytes, and no v1 header is shorter
+    let mut start = [0u8; 12];
+    stream.read_exact(&mut start).await?;
+    if &start == V2_SIGNATURE {
+        read_v2(stream).await
+    } else if start.starts_with(b"PROXY ") {
+        read_v1(stream, &start).await
+    } else {
+        Err(invalid("the connection didn't start with a PROXY protocol header"))
+    }
+}
+
+async fn read_v1<S: AsyncRead + Unpin>(stream: &mut S, start: &[u8]) -> std::io::Result<ProxyHeader> {
+    let mut line = start.to_vec();
+    // The line is read a byte at a time so that none of the request after it is consumed
+    while !line.ends_with(b"\r\n") {
+        if line.len() == V1_MAX_LENGTH {
+            return Err(invalid("the PROXY protocol v1 header is too long"));
+        }
+        line.push(stream.read_u8().await?);
+    }
+    let line = std::str::from_utf8(&line[..line.len() - 2])
+        .map_err(|_| invalid("the PROXY protocol v1 header isn't ASCII"))?;
+
+    let mut fields = line.split(' ').skip(1);
+    match fields.next() {
+        Some("UNKNOWN") => Ok(ProxyHeader::default()),
+        Some(family @ ("TCP4" | "TCP6")) => {
+            let mut next = || fields.next().ok_or_else(|| invalid("the PROXY protocol v1 header is missing fields"));
+            let (source_ip, destination_ip) = (next()?, next()?);
+            let (source_port, destination_port) = (next()?, next()?);
+            if fields.next().is_some() {
+                return Err(invalid("the PROXY protocol v1 header has too many fields"));
+            }
+            let source = parse_v1_addr(source_ip, source_port)?;
+            let destination = parse_v1_addr(destination_ip, destination_port)?;
+            let ipv6 = family == "TCP6";
+            if source.is_ipv6() != ipv6 || destination.is_ipv6() != ipv6 {
+                return Err(invalid("the addresses in the PROXY protocol v1 header don't match its family"));
+            }
+            Ok(ProxyHeader {
+                source: Some(source),
+                destination: Some(destination),
+                tlvs: Vec::new()
+            })
+        },
+        _ => Err(invalid("unknown PROXY protocol v1 address family"))
+    }
+}
+
+fn parse_v1_addr(ip: &str, port: &str) -> std::io::Result<SocketAddr> {
+    let ip: IpAddr = ip.parse().map_err(|_| invalid("invalid address in the PROXY protocol v1 header"))?;
+    let port: u16 = port.parse().map_err(|_| invalid("invalid port in the PROXY protocol v1 header"))?;
+    Ok(SocketAddr::new(ip, port))
+}
+
+async fn read_v2<S: AsyncRead + Unpin>(stream: &mut S) -> std::io::Result<ProxyHeader> {
+    let version_command = stream.read_u8().await?;
+    let family = stream.read_u8().await?;
+    let length = stream.read_u16().await? as usize;
+    let mut payload = vec![0u8; length];
+    stream.read_exact(&mut payload).await?;
+
+    if version_command >> 4 != 2 {
+        return Err(invalid("unsupported PROXY protocol version"));
+    }
+    let (addresses_length, addresses) = match family >> 4 {
+        0x1 => (12, read_v2_addrs(&payload, 4)),
+        0x2 => (36, read_v2_addrs(&payload, 16)),
+        0x3 => (216, None),
+        _ => (0, None)
+    };
+    if payload.len() < addresses_length {
+        return Err(invalid("the PROXY protocol v2 header is too short for its addresses"));
+    }
+    let tlvs = parse_tlvs(&payload[addresses_length..])?;
+    // LOCAL connections come from the load balancer itself, such as health checks
+    let (source, destination) = match (version_command & 0x0F, addresses) {
+        (0x1, Some((source, destination))) => (Some(source), Some(destination)),
+        (0x0 | 0x1, _) => (None, None),
+        _ => return Err(invalid("unknown PROXY protocol v2 command"))
+    };
+    Ok(ProxyHeader { source, destination, tlvs })
+}
+
+/// Reads the source and destination of an IPv4 or IPv6 address block.
+fn read_v2_addrs(payload: &[u8], ip_length: usize) -> Option<(SocketAddr, SocketAddr)> {
+    let block = payload.get(..ip_length * 2 + 4)?;
+    let ip = |bytes: &[u8]| match ip_length {
+        4 => IpAddr::from(Ipv4Addr::from(<[u8; 4]>::try_from(bytes).unwrap())),
+        _ => IpAddr::from(Ipv6Addr::from(<[u8; 16]>::try_from(bytes).unwrap()))
+    };
+    let port = |offset: usize| u16::from_be_bytes([block[offset], block[offset + 1]]);
+    let source = SocketAddr::new(ip(&block[..ip_length]), port(ip_length * 2));
+    let destination = SocketAddr::new(ip(&block[ip_length..ip_length * 2]), port(ip_length * 2 + 2));
+    Some((source, destination))
+}
+
+fn parse_tlvs(mut bytes: &[u8]) -> std::io::Result<Vec<ProxyTlv>> {
+    let mut tlvs = Vec::new();
+    while !bytes.is_empty() {
+        if bytes.len() < 3 {
+            return Err(invalid("truncated TLV in the PROXY protocol v2 header"));
+        }
+        let length = u16::from_be_bytes([bytes[1], bytes[2]]) as usize;
+        let value = bytes
+            .get(3..3 + length)
+            .ok_or_else(|| invalid("truncated TLV in the PROXY protocol v2 header"))?;
+        tlvs.push(ProxyTlv { kind: bytes[0], value: value.to_vec() });
+        bytes = &bytes[3 + length..];
+    }
+    Ok(tlvs)
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reads the header off the input, along with whatever's left after it.
+    async fn read(input: &[u8]) -> (std::io::Result<ProxyHeader>, Vec<u8>) {
+        let mut stream = input;
+        let header = read_header(&mut stream).await;
+        (header, stream.to_vec())
+    }
+
+    fn addr(addr: &str) -> Option<SocketAddr> {
+        Some(addr.parse().unwrap())
+    }
+
+    fn v2(command: u8, family: u8, payload: &[u8]) -> Vec<u8> {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend_from_slice(&[0x20 | command, family]);
+        header.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        header.extend_from_slice(payload);
+        header
+    }
+
+    const IPV4_BLOCK: [u8; 12] = [192, 168, 0, 1, 10, 0, 0, 1, 0x1F, 0x90, 0x00, 0x50];
+
+    #[tokio::test]
+    async fn v1_tcp4_and_tcp6_headers_are_read() {
+        let (header, rest) = read(b"PROXY TCP4 192.168.0.1 10.0.0.1 8080 80\r\nGET / HTTP/1.1\r\n").await;
+        let header = header.unwrap();
+        assert_eq!((header.source, header.destination), (addr("192.168.0.1:8080"), addr("10.0.0.1:80")));
+        assert_eq!(rest, b"GET / HTTP/1.1\r\n");
+
+        let header = read(b"PROXY TCP6 2001:db8::1 ::1 8080 443\r\n").await.0.unwrap();
+        assert_eq!((header.source, header.destination), (addr("[2001:db8::1]:8080"), addr("[::1]:443")));
+    }
+
+    #[tokio::test]
+    async fn v1_unknown_header_has_no_addresses() {
+        let (header, rest) = read(b"PROXY UNKNOWN ffff::1 ffff::2 1 2\r\nrest").await;
+        let header = header.unwrap();
+        assert_eq!((header.source, header.destination), (None, None));
+        assert_eq!(rest, b"rest");
+    }
+
+    #[tokio::test]
+    async fn invalid_v1_headers_are_refused() {
+        let too_long = format!("PROXY UNKNOWN {}\r\n", "a".repeat(V1_MAX_LENGTH));
+        for input in [
+            too_long.as_bytes(),
+            b"PROXY TCP4 192.168.0.1 10.0.0.1 8080\r\n",
+            b"PROXY TCP4 192.168.0.1 10.0.0.1 8080 80 1\r\n",
+            b"PROXY TCP4 192.168.0.1 10.0.0.1 8080 65536\r\n",
+            b"PROXY TCP4 ::1 ::1 8080 80\r\n",
+            b"PROXY TCP6 192.168.0.1 10.0.0.1 8080 80\r\n",
+            b"PROXY UDP4 192.168.0.1 10.0.0.1 8080 80\r\n",
+            b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n"
+        ] {
+            let error = read(input).await.0.unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidData, "{}", String::from_utf8_lossy(input));
+        }
+    }
+
+    #[tokio::test]
+    async fn v2_proxy_headers_are_read_with_their_tlvs() {
+        let mut payload = IPV4_BLOCK.to_vec();
+        payload.extend_from_slice(&[ProxyTlv::AUTHORITY, 0, 9]);
+        payload.extend_from_slice(b"localhost");
+        payload.extend_from_slice(&[ProxyTlv::NOOP, 0, 0]);
+        let mut input = v2(0x1, 0x11, &payload);
+        input.extend_from_slice(b"GET");
+        let (header, rest) = read(&input).await;
+        let header = header.unwrap();
+        assert_eq!((header.source, header.destination), (addr("192.168.0.1:8080"), addr("10.0.0.1:80")));
+        assert_eq!(header.tlv(ProxyTlv::AUTHORITY), Some(&b"localhost"[..]));
+        assert_eq!(header.tlv(ProxyTlv::NOOP), Some(&[][..]));
+        assert_eq!(rest, b"GET");
+
+        let mut payload = Ipv6Addr::LOCALHOST.octets().to_vec();
+        payload.extend_from_slice(&"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+        payload.extend_from_slice(&[0x1F, 0x90, 0x01, 0xBB]);
+        let header = read(&v2(0x1, 0x21, &payload)).await.0.unwrap();
+        assert_eq!((header.source, header.destination), (addr("[::1]:8080"), addr("[2001:db8::1]:443")));
+    }
+
+    #[tokio::test]
+    async fn v2_local_headers_have_no_addresses() {
+        let header = read(&v2(0x0, 0x11, &IPV4_BLOCK)).await.0.unwrap();
+        assert_eq!((header.source, header.destination), (None, None));
+        let header = read(&v2(0x0, 0x00, &[])).await.0.unwrap();
+        assert_eq!((header.source, header.destination), (None, None));
+    }
+
+    #[tokio::test]
+    async fn invalid_v2_headers_are_refused() {
+        let truncated_length = [IPV4_BLOCK.as_slice(), &[ProxyTlv::AUTHORITY, 0]].concat();
+        let truncated_value = [IPV4_BLOCK.as_slice(), &[ProxyTlv::AUTHORITY, 0, 9, b'l']].concat();
+        for input in [
+            v2(0x1, 0x11, &truncated_length),
+            v2(0x1, 0x11, &truncated_value),
+            v2(0x1, 0x11, &IPV4_BLOCK[..8]),
+            v2(0x1, 0x21, &IPV4_BLOCK),
+            v2(0x2, 0x11, &IPV4_BLOCK)
+        ] {
+            let error = read(&input).await.0.unwrap_err();
+            assert_eq!(error.kind(), ErrorKind::InvalidData, "{input:?}");
+        }
+        // The header announces more than the connection sends
+        let mut input = v2(0x1, 0x11, &IPV4_BLOCK);
+        input.truncate(input.len() - 1);
+        assert_eq!(read(&input).await.0.unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn tlvs_are_read_in_order() {
+        let tlvs = parse_tlvs(&[ProxyTlv::ALPN, 0, 2, b'h', b'2', ProxyTlv::UNIQUE_ID, 0, 1, 7]).unwrap();
+        assert_eq!(tlvs, [
+            ProxyTlv { kind: ProxyTlv::ALPN, value: b"h2".to_vec() },
+            ProxyTlv { kind: ProxyTlv::UNIQUE_ID, value: vec![7] }
+        ]);
+        assert!(parse_tlvs(&[]).unwrap().is_empty());
+        assert!(parse_tlvs(&[ProxyTlv::ALPN]).is_err());
+    }
+}
@@ -0,0 +1,281 @@
+use crate::listener::ListenerInfo;
+use crate::protocol::ConnectionInfo;
+use crate::request::{HttpScheme, PeerAddr};
+use std::future::Future;
+use std::io::IoSlice;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, BufReader, ReadBuf, ReadHalf, WriteHalf};
+use tokio::net::TcpStream;
+use tokio::time::{Instant, Sleep};
+#[cfg(unix)]
+use tokio::net::UnixStream;
+
+pub(crate) type ConnectionReader = BufReader<Rewind<ReadHalf<ConnectionStream>>>;
+pub(crate) type ConnectionWriter = WriteHalf<ConnectionStream>;
+
+/// Anything requests can be read from and responses written to, such as an in-memory pipe.
+pub(crate) trait Transport: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Transport for T {}
+
+/// An accepted connection, which may have TLS terminated on it.
+pub(crate) enum ConnectionStream {
+    Plain(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+    #[cfg(feature = "tls")]
+    Tls(Box<tokio_rustls::server::TlsStream<ConnectionStream>>),
+    /// A transport handed over by the user instead of accepted by a listener
+    Transport(Box<dyn Transport>)
+}
+
+impl ConnectionStream {
+    pub(crate) fn scheme(&self) -> HttpScheme {
+        match self {
+            #[cfg(feature = "tls")]
+            ConnectionStream::Tls(_) => HttpScheme::HTTPS,
+            _ => HttpScheme::HTTP
+        }
+    }
+
+    /// The protocol agreed on through ALPN during the TLS handshake.
+    pub(crate) fn alpn_protocol(&self) -> Option<&[u8]> {
+        match self {
+            #[cfg(feature = "tls")]
+            ConnectionStream::Tls(stream) => stream.get_ref().1.alpn_protocol(),
+            _ => None
+        }
+    }
+
+    pub(crate) fn info(&self, peer_addr: PeerAddr, listener: Option<ListenerInfo>) -> ConnectionInfo {
+        ConnectionInfo {
+            peer_addr,
+            scheme: self.scheme(),
+            listener,
+            proxy: None,
+            #[cfg(unix)]
+            peer_credentials: self.peer_credentials(),
+            #[cfg(feature = "tls")]
+            peer_certificate: match self {
+                ConnectionStream::Tls(stream) => stream
+                    .get_ref()
+                    .1
+                    .peer_certificates()
+                    .and_then(crate::tls::PeerCertificate::from_chain),
+                _ => None
+            }
+        }
+    }
+
+    /// Who is on the other end of a Unix socket, as reported by the kernel.
+    #[cfg(unix)]
+    fn peer_credentials(&self) -> Option<crate::extractor::peer::PeerCredentials> {
+        match self {
+            ConnectionStream::Unix(stream) => stream.peer_cred().ok().map(Into::into),
+            #[cfg(feature = "tls")]
+            ConnectionStream::Tls(stream) => stream.get_ref().0.peer_credentials(),
+            _ => None
+        }
+    }
+}
+
+/// Wraps accepted sockets in TLS when it's configured.
+#[derive(Clone, Default)]
+pub(crate) struct ConnectionAcceptor {
+    #[cfg(feature = "tls")]
+    pub(crate) tls: Option<tokio_rustls::TlsAcceptor>
+}
+
+impl ConnectionAcceptor {
+    pub(crate) fn is_tls(&self) -> bool {
+        #[cfg(feature = "tls")]
+        return self.tls.is_some();
+        #[cfg(not(feature = "tls"))]
+        false
+    }
+
+    pub(crate) async fn accept(&self, stream: ConnectionStream) -> std::io::Result<ConnectionStream> {
+        #[cfg(feature = "tls")]
+        if let Some(tls) = &self.tls {
+            let stream = tls.accept(stream).await?;
+            return Ok(ConnectionStream::Tls(Box::new(stream)));
+        }
+        Ok(stream)
+    }
+}
+
+impl AsyncRead for ConnectionStream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            ConnectionStream::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(unix)]
+            ConnectionStream::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(feature = "tls")]
+            ConnectionStream::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+            ConnectionStream::Transport(stream) => Pin::new(stream).poll_read(cx, buf)
+        }
+    }
+}
+
+impl AsyncWrite for ConnectionStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        match self.get_mut() {
+            ConnectionStream::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(unix)]
+            ConnectionStream::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(feature = "tls")]
+            ConnectionStream::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+            ConnectionStream::Transport(stream) => Pin::new(stream).poll_write(cx, buf)
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            ConnectionStream::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(unix)]
+            ConnectionStream::Unix(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(feature = "tls")]
+            ConnectionStream::Tls(stream) => Pin::new(stream).poll_flush(cx),
+            ConnectionStream::Transport(stream) => Pin::new(stream).poll_flush(cx)
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            ConnectionStream::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            #[cfg(unix)]
+            ConnectionStream::Unix(stream) => Pin::new(stream).poll_shutdown(cx),
+            #[cfg(feature = "tls")]
+            ConnectionStream::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+            ConnectionStream::Transport(stream) => Pin::new(stream).poll_shutdown(cx)
+        }
+    }
+
+    fn poll_write_vectored(self: Pin<&mut Self>, cx: &mut Context<'_>, bufs: &[IoSlice<'_>]) -> Poll<std::io::Result<usize>> {
+        match self.get_mut() {
+            ConnectionStream::Plain(stream) => Pin::new(stream).poll_write_vectored(cx, bufs),
+            #[cfg(unix)]
+            ConnectionStream::Unix(stream) => Pin::new(stream).poll_write_vectored(cx, bufs),
+            #[cfg(feature = "tls")]
+            ConnectionStream::Tls(stream) => Pin::new(stream).poll_write_vectored(cx, bufs),
+            ConnectionStream::Transport(stream) => Pin::new(stream).poll_write_vectored(cx, bufs)
+        }
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        match self {
+            ConnectionStream::Plain(stream) => stream.is_write_vectored(),
+            #[cfg(unix)]
+            ConnectionStream::Unix(stream) => stream.is_write_vectored(),
+            #[cfg(feature = "tls")]
+            ConnectionStream::Tls(stream) => stream.is_write_vectored(),
+            ConnectionStream::Transport(stream) => stream.is_write_vectored()
+        }
+    }
+}
+
+/// Reads bytes that were already taken out of a reader before the reader itself, such as
+/// the ones read to tell HTTP/2 connections apart.
+pub(crate) struct Rewind<R> {
+    prefix: Vec<u8>,
+    position: usize,
+    inner: R
+}
+
+impl<R> Rewind<R> {
+    pub(crate) fn new(inner: R) -> Self {
+        Rewind { prefix: Vec::new(), position: 0, inner }
+    }
+
+    /// Has the bytes read before anything else that's left in the reader.
+    pub(crate) fn rewind(&mut self, bytes: Vec<u8>) {
+        let mut prefix = bytes;
+        prefix.extend_from_slice(&self.prefix[self.position..]);
+        self.prefix = prefix;
+        self.position = 0;
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for Rewind<R> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        if this.position < this.prefix.len() {
+            let length = buf.remaining().min(this.prefix.len() - this.position);
+            buf.put_slice(&this.prefix[this.position..this.position + length]);
+            this.position += length;
+            if this.position == this.prefix.len() {
+                this.prefix = Vec::new();
+                this.position = 0;
+            }
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut this.inner).poll_read(cx, buf)
+    }
+}
+
+/// Fails writes that stay pending for longer than the timeout, which happens when the client
+/// stops reading. Only time spent blocked on the client counts, so slowly produced responses
+/// aren't cut short.
+pub(crate) struct WriteTimeout<W> {
+    inner: W,
+    timeout: Duration,
+    deadline: Pin<Box<Sleep>>,
+    armed: bool
+}
+
+impl<W: AsyncWrite + Unpin> WriteTimeout<W> {
+    pub(crate) fn new(inner: W, timeout: Duration) -> Self {
+        WriteTimeout {
+            inner,
+            timeout,
+            deadline: Box::pin(tokio::time::sleep(timeout)),
+            armed: false
+        }
+    }
+
+    fn poll_within_deadline<T>(
+        &mut self,
+        cx: &mut Context<'_>,
+        poll: impl FnOnce(Pin<&mut W>, &mut Context<'_>) -> Poll<std::io::Result<T>>
+    ) -> Poll<std::io::Result<T>> {
+        if let Poll::Ready(result) = poll(Pin::new(&mut self.inner), cx) {
+            self.armed = false;
+            return Poll::Ready(result);
+        }
+        if !self.armed {
+            self.deadline.as_mut().reset(Instant::now() + self.timeout);
+            self.armed = true;
+        }
+        match self.deadline.as_mut().poll(cx) {
+            Poll::Ready(()) => Poll::Ready(Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                "the client stopped reading the response"
+            ))),
+            Poll::Pending => Poll::Pending
+        }
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for WriteTimeout<W> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        self.get_mut().poll_within_deadline(cx, |inner, cx| inner.poll_write(cx, buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.get_mut().poll_within_deadline(cx, |inner, cx| inner.poll_flush(cx))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.get_mut().poll_within_deadline(cx, |inner, cx| inner.poll_shutdown(cx))
+    }
+
+    fn poll_write_vectored(self: Pin<&mut Self>, cx: &mut Context<'_>, bufs: &[IoSlice<'_>]) -> Poll<std::io::Result<usize>> {
+        self.get_mut().poll_within_deadline(cx, |inner, cx| inner.poll_write_vectored(cx, bufs))
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+}
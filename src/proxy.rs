@@ -0,0 +1,83 @@
+use crate::extractor::{ExtractorError, FromRequest};
+use crate::request::HttpRequest;
+use async_trait::async_trait;
+use std::net::SocketAddr;
+
+/// The PROXY protocol header a load balancer sent ahead of the connection, on listeners
+/// with [proxy_protocol](crate::listener::ListenerConfig::proxy_protocol) enabled.
+#[derive(Debug, Clone, Default)]
+pub struct ProxyHeader {
+    pub(crate) source: Option<SocketAddr>,
+    pub(crate) destination: Option<SocketAddr>,
+    pub(crate) tlvs: Vec<ProxyTlv>
+}
+
+impl ProxyHeader {
+    /// The client the load balancer accepted the connection from. Missing for health checks
+    /// and connections the load balancer couldn't describe.
+    pub fn source(&self) -> Option<SocketAddr> {
+        self.source
+    }
+
+    /// The address the client connected to.
+    pub fn destination(&self) -> Option<SocketAddr> {
+        self.destination
+    }
+
+    /// The TLVs of a v2 header, in the order they were sent. Always empty for v1.
+    pub fn tlvs(&self) -> &[ProxyTlv] {
+        &self.tlvs
+    }
+
+    /// The value of the first TLV of the given type, such as [ProxyTlv::AUTHORITY].
+    pub fn tlv(&self, kind: u8) -> Option<&[u8]> {
+        self.tlvs.iter().find(|tlv| tlv.kind == kind).map(ProxyTlv::value)
+    }
+}
+
+/// A type-length-value field of a PROXY protocol v2 header.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ProxyTlv {
+    pub(crate) kind: u8,
+    pub(crate) value: Vec<u8>
+}
+
+impl ProxyTlv {
+    pub const ALPN: u8 = 0x01;
+    pub const AUTHORITY: u8 = 0x02;
+    pub const CRC32C: u8 = 0x03;
+    pub const NOOP: u8 = 0x04;
+    pub const UNIQUE_ID: u8 = 0x05;
+    pub const SSL: u8 = 0x20;
+    pub const NETNS: u8 = 0x30;
+    /// Carries the VPC endpoint id on AWS Network Load Balancers
+    pub const AWS: u8 = 0xEA;
+
+    pub fn kind(&self) -> u8 {
+        self.kind
+    }
+
+    pub fn value(&self) -> &[u8] {
+        &self.value
+    }
+}
+
+#[async_trait]
+impl FromRequest for ProxyHeader {
+    async fn from_request(_index: usize, request: &mut HttpRequest) -> Result<Self, ExtractorError>
+    where
+        Self: Sized
+    {
+        request.connection.proxy.clone().ok_or(ExtractorError::MissingProxyHeader)
+    }
+}
+
+#[async_trait]
+impl FromRequest for Option<ProxyHeader> {
+    async fn from_request(_index: usize, request: &mut HttpRequest) -> Result<Self, ExtractorError>
+    where
+        Self: Sized
+    {
+        Ok(request.connection.proxy.clone())
+    }
+}
@@ -0,0 +1,210 @@
+use crate::parser::alpha::is_token;
+use crate::request::HttpHeader;
+use thiserror::Error;
+
+/// The header fields of a request or response, in the order they were added. A name may
+/// hold several values, such as `Set-Cookie`, and names are compared case-insensitively.
+#[derive(Debug, Clone, Default)]
+pub struct HeaderMap {
+    entries: Vec<(HttpHeader, String)>
+}
+
+#[derive(Debug, Error, Eq, PartialEq)]
+pub enum HeaderError {
+    #[error("Header values can't contain CR, LF or NUL characters")]
+    InvalidValue,
+    #[error("\"{0}\" is not a valid header name")]
+    InvalidName(String)
+}
+
+impl HeaderMap {
+    pub fn new() -> Self {
+        HeaderMap { entries: Vec::new() }
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        HeaderMap { entries: Vec::with_capacity(capacity) }
+    }
+
+    /// How many values the map holds, counting every value of repeated names.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The first value of the given name.
+    pub fn get(&self, header: &HttpHeader) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(name, _)| name == header)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Every value of the given name, in the order they were added.
+    pub fn get_all<'a>(&'a self, header: &'a HttpHeader) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries
+            .iter()
+            .filter(move |(name, _)| name == header)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn contains_key(&self, header: &HttpHeader) -> bool {
+        self.entries.iter().any(|(name, _)| name == header)
+    }
+
+    /// Sets the value of the given name, replacing all of its previous values, and returns
+    /// the first of them.
+    ///
+    /// # Panics
+    ///
+    /// If the name or value could be used to inject other fields, see [HeaderMap::try_insert].
+    pub fn insert(&mut self, header: HttpHeader, value: impl Into<String>) -> Option<String> {
+        self.try_insert(header, value).unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    /// Adds a value to the given name, keeping the ones it already has.
+    ///
+    /// # Panics
+    ///
+    /// If the name or value could be used to inject other fields, see [HeaderMap::try_append].
+    pub fn append(&mut self, header: HttpHeader, value: impl Into<String>) {
+        self.try_append(header, value).unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    /// Like [HeaderMap::insert], but fails instead of panicking if the value has a line
+    /// break or the name isn't a valid token, such as when they come from user input.
+    pub fn try_insert(&mut self, header: HttpHeader, value: impl Into<String>) -> Result<Option<String>, HeaderError> {
+        let value = value.into();
+        validate(&header, &value)?;
+        let header = header.canonical();
+        // The first value keeps its position, so that replacing it doesn't reorder the fields
+        let Some(index) = self.entries.iter().position(|(name, _)| *name == header) else {
+            self.entries.push((header, value));
+            return Ok(None);
+        };
+        let previous = std::mem::replace(&mut self.entries[index].1, value);
+        let rest = self.entries.split_off(index + 1);
+        self.entries.extend(rest.into_iter().filter(|(name, _)| *name != header));
+        Ok(Some(previous))
+    }
+
+    /// Like [HeaderMap::append], but fails instead of panicking if the value has a line
+    /// break or the name isn't a valid token.
+    pub fn try_append(&mut self, header: HttpHeader, value: impl Into<String>) -> Result<(), HeaderError> {
+        let value = value.into();
+        validate(&header, &value)?;
+        self.entries.push((header.canonical(), value));
+        Ok(())
+    }
+
+    /// Removes every value of the given name, returning the first of them.
+    pub fn remove(&mut self, header: &HttpHeader) -> Option<String> {
+        let mut first = None;
+        self.entries.retain_mut(|(name, value)| {
+            if name != header {
+                return true;
+            }
+            if first.is_none() {
+                first = Some(std::mem::take(value));
+            }
+            false
+        });
+        first
+    }
+
+    /// Every value along with its name, in the order they were added. Repeated names come
+    /// up once per value.
+    pub fn iter(&self) -> impl Iterator<Item = (&HttpHeader, &str)> {
+        self.entries.iter().map(|(name, value)| (name, value.as_str()))
+    }
+}
+
+/// Rejects what would let a value end the field it's in and start another one.
+fn validate(header: &HttpHeader, value: &str) -> Result<(), HeaderError> {
+    if value.bytes().any(|byte| matches!(byte, b'\r' | b'\n' | b'\0')) {
+        return Err(HeaderError::InvalidValue);
+    }
+    match header {
+        HttpHeader::Custom(name) if !is_token(name) => Err(HeaderError::InvalidName(name.clone())),
+        _ => Ok(())
+    }
+}
+
+impl IntoIterator for HeaderMap {
+    type Item = (HttpHeader, String);
+    type IntoIter = std::vec::IntoIter<(HttpHeader, String)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a HeaderMap {
+    type Item = (&'a HttpHeader, &'a str);
+    type IntoIter = Box<dyn Iterator<Item = (&'a HttpHeader, &'a str)> + 'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        Box::new(self.iter())
+    }
+}
+
+/// Appends every field, so that repeated names keep all of their values.
+impl<V: Into<String>> Extend<(HttpHeader, V)> for HeaderMap {
+    fn extend<I: IntoIterator<Item = (HttpHeader, V)>>(&mut self, fields: I) {
+        for (header, value) in fields {
+            self.append(header, value);
+        }
+    }
+}
+
+impl<V: Into<String>> FromIterator<(HttpHeader, V)> for HeaderMap {
+    fn from_iter<I: IntoIterator<Item = (HttpHeader, V)>>(fields: I) -> Self {
+        let mut map = HeaderMap::new();
+        map.extend(fields);
+        map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn custom_name_of_a_well_known_header_replaces_it() {
+        let mut headers = HeaderMap::new();
+        headers.insert(HttpHeader::ContentLength, "5");
+        headers.insert(HttpHeader::Custom("content-LENGTH".to_string()), "6");
+        assert_eq!(headers.iter().collect::<Vec<_>>(), [(&HttpHeader::ContentLength, "6")]);
+
+        headers.append(HttpHeader::Custom("Set-Cookie".to_string()), "a=1");
+        headers.append(HttpHeader::Custom("set-cookie".to_string()), "b=2");
+        let set_cookie = HttpHeader::from_name("SET-COOKIE");
+        let cookies: Vec<&str> = headers.get_all(&set_cookie).collect();
+        assert_eq!(cookies, ["a=1", "b=2"]);
+    }
+
+    #[test]
+    fn names_compare_hash_and_order_alike() {
+        let custom = HttpHeader::Custom("Content-Length".to_string());
+        assert_eq!(custom, HttpHeader::ContentLength);
+        assert_eq!(custom.cmp(&HttpHeader::ContentLength), std::cmp::Ordering::Equal);
+        let set: HashSet<HttpHeader> = [custom, HttpHeader::ContentLength, HttpHeader::Custom("x-id".to_string())].into();
+        assert_eq!(set.len(), 2);
+        assert!(set.contains(&HttpHeader::Custom("X-ID".to_string())));
+        assert!(HttpHeader::Accept < HttpHeader::Custom("x-id".to_string()));
+        assert!(HttpHeader::Custom("age".to_string()) < HttpHeader::Allow);
+    }
+
+    #[test]
+    fn injected_fields_are_refused() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(headers.try_insert(HttpHeader::Location, "/\r\nSet-Cookie: a=1"), Err(HeaderError::InvalidValue));
+        let name = HttpHeader::Custom("X-Id: 1\r\n".to_string());
+        assert!(matches!(headers.try_append(name, "1"), Err(HeaderError::InvalidName(_))));
+        assert!(headers.is_empty());
+    }
+}
@@ -1,28 +1,40 @@
+pub mod header;
+
 use std::collections::HashMap;
 use std::fmt::Display;
+use std::hash::{Hash, Hasher};
 use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::sync::OnceCell;
 use crate::router::flow::RequestFlow;
-use crate::parser::body::LazyBodyReader;
+use crate::parser::body::{BodyError, LazyBodyReader};
+use crate::protocol::ConnectionInfo;
+
+pub use header::HeaderMap;
+
+#[deprecated(note = "Use HeaderMap, which keeps every value of repeated fields")]
+pub type HttpHeaderMap = HeaderMap;
 
 pub struct HttpRequest {
     pub protocol: HttpProtocol,
     pub scheme: HttpScheme,
     pub path: String,
     pub method: HttpMethod,
-    pub headers: HttpHeaderMap,
+    pub headers: HeaderMap,
     pub flow: OnceCell<Arc<RequestFlow>>,
-    pub peer_addr: SocketAddr,
-    pub content_length: usize,
+    pub peer_addr: PeerAddr,
+    pub content_length: Option<usize>,
     pub body_reader: LazyBodyReader,
-    pub(crate) path_values: OnceCell<RoutePathValues>
+    pub(crate) path_values: OnceCell<RoutePathValues>,
+    pub(crate) version: HttpVersion,
+    pub(crate) keep_alive: bool,
+    pub(crate) connection: Arc<ConnectionInfo>
 }
 
 unsafe impl Send for HttpRequest {}
 
 impl HttpRequest {
-    pub async fn read_body(&mut self) -> &[u8] {
+    pub async fn read_body(&mut self) -> Result<&[u8], BodyError> {
         self.body_reader.read_all().await
     }
 
@@ -45,7 +57,13 @@ impl HttpRequest {
     }
 }
 
-pub type HttpHeaderMap = HashMap<HttpHeader, String>;
+/// The exact version a request was sent with, which decides how its response can be framed.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum HttpVersion {
+    Http10,
+    Http11,
+    Http2
+}
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
 pub enum HttpProtocol {
@@ -53,6 +71,36 @@ pub enum HttpProtocol {
     HTTP2 = 2
 }
 
+/// Where a request came from.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum PeerAddr {
+    Ip(SocketAddr),
+    /// A client connected through a Unix socket, which can be identified by its
+    /// [PeerCredentials](crate::extractor::peer::PeerCredentials)
+    Unix,
+    /// The connection was handed over through [serve_connection](crate::serve_connection)
+    Unknown
+}
+
+impl PeerAddr {
+    pub fn ip(&self) -> Option<SocketAddr> {
+        match self {
+            PeerAddr::Ip(addr) => Some(*addr),
+            _ => None
+        }
+    }
+}
+
+impl Display for PeerAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PeerAddr::Ip(addr) => write!(f, "{}", addr),
+            PeerAddr::Unix => write!(f, "unix socket"),
+            PeerAddr::Unknown => write!(f, "unknown peer")
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
 pub enum HttpScheme {
     HTTP,
@@ -82,6 +130,18 @@ pub enum HttpMethod {
 }
 
 impl HttpMethod {
+    /// Every method, in the order they are listed in `Allow` headers
+    pub(crate) const ALL: [HttpMethod; 8] = [
+        HttpMethod::GET,
+        HttpMethod::POST,
+        HttpMethod::PUT,
+        HttpMethod::DELETE,
+        HttpMethod::OPTIONS,
+        HttpMethod::HEAD,
+        HttpMethod::PATCH,
+        HttpMethod::TRACE
+    ];
+
     pub fn from_str(method: &str) -> Option<HttpMethod> {
         match method {
             "GET" => Some(HttpMethod::GET),
@@ -97,7 +157,9 @@ impl HttpMethod {
     }
 }
 
-#[derive(Debug, Ord, PartialOrd, Eq, PartialEq, Hash, Clone)]
+/// The name of a header field. Names are compared case-insensitively, including custom ones,
+/// and a custom name equals the well-known header it spells.
+#[derive(Debug, Clone)]
 pub enum HttpHeader {
     AIM,
     Accept,
@@ -106,6 +168,7 @@ pub enum HttpHeader {
     AcceptEncoding,
     AcceptLanguage,
     AccessControlRequestMethod,
+    Allow,
     Authorization,
     CacheControl,
     Connection,
@@ -153,57 +216,63 @@ pub enum HttpHeader {
 
 impl HttpHeader {
     pub fn name(&self) -> String {
+        self.as_str().to_string()
+    }
+
+    /// The name as sent on the wire, in its usual case for well-known headers.
+    pub fn as_str(&self) -> &str {
         match self {
-            HttpHeader::AIM => "AIM".to_string(),
-            HttpHeader::Accept => "Accept".to_string(),
-            HttpHeader::AcceptCharset => "Accept-Charset".to_string(),
-            HttpHeader::AcceptDatetime => "Accept-Datetime".to_string(),
-            HttpHeader::AcceptEncoding => "Accept-Encoding".to_string(),
-            HttpHeader::AcceptLanguage => "Accept-Language".to_string(),
-            HttpHeader::AccessControlRequestMethod => "Access-Control-Request-Method".to_string(),
-            HttpHeader::Authorization => "Authorization".to_string(),
-            HttpHeader::CacheControl => "Cache-Control".to_string(),
-            HttpHeader::Connection => "Connection".to_string(),
-            HttpHeader::ContentEncoding => "Content-Encoding".to_string(),
-            HttpHeader::ContentLength => "Content-Length".to_string(),
-            HttpHeader::ContentMd5 => "Content-MD5".to_string(),
-            HttpHeader::ContentType => "Content-Type".to_string(),
-            HttpHeader::Cookie => "Cookie".to_string(),
-            HttpHeader::Date => "Date".to_string(),
-            HttpHeader::Expect => "Expect".to_string(),
-            HttpHeader::Forwarded => "Forwarded".to_string(),
-            HttpHeader::From => "From".to_string(),
-            HttpHeader::Host => "Host".to_string(),
-            HttpHeader::Http2Settings => "HTTP2-Settings".to_string(),
-            HttpHeader::IfMatch => "If-Match".to_string(),
-            HttpHeader::IfModifiedSince => "If-Modified-Since".to_string(),
-            HttpHeader::IfNoneMatch => "If-None-Match".to_string(),
-            HttpHeader::IfRange => "If-Range".to_string(),
-            HttpHeader::IfUnmodifiedSince => "If-Unmodified-Since".to_string(),
-            HttpHeader::Location => "Location".to_string(),
-            HttpHeader::MaxForwards => "Max-Forwards".to_string(),
-            HttpHeader::Origin => "Origin".to_string(),
-            HttpHeader::Pragma => "Pragma".to_string(),
-            HttpHeader::Prefer => "Prefer".to_string(),
-            HttpHeader::ProxyAuthorization => "Proxy-Authorization".to_string(),
-            HttpHeader::Range => "Range".to_string(),
-            HttpHeader::Referer => "Referer".to_string(),
-            HttpHeader::Server => "Server".to_string(),
-            HttpHeader::Te => "TE".to_string(),
-            HttpHeader::Trailer => "Trailer".to_string(),
-            HttpHeader::TransferEncoding => "Transfer-Encoding".to_string(),
-            HttpHeader::UserAgent => "User-Agent".to_string(),
-            HttpHeader::Upgrade => "Upgrade".to_string(),
-            HttpHeader::Via => "Via".to_string(),
-            HttpHeader::Warning => "Warning".to_string(),
-            HttpHeader::Custom(name) => name.to_string(),
-
-            HttpHeader::PseudoScheme => ":scheme".to_string(),
-            HttpHeader::PseudoMethod => ":method".to_string(),
-            HttpHeader::PseudoAuthority => ":authority".to_string(),
-            HttpHeader::PseudoPath => ":path".to_string(),
-            HttpHeader::PseudoStatus => ":status".to_string(),
-            HttpHeader::PseudoProtocol => ":protocol".to_string()
+            HttpHeader::AIM => "AIM",
+            HttpHeader::Accept => "Accept",
+            HttpHeader::AcceptCharset => "Accept-Charset",
+            HttpHeader::AcceptDatetime => "Accept-Datetime",
+            HttpHeader::AcceptEncoding => "Accept-Encoding",
+            HttpHeader::AcceptLanguage => "Accept-Language",
+            HttpHeader::AccessControlRequestMethod => "Access-Control-Request-Method",
+            HttpHeader::Allow => "Allow",
+            HttpHeader::Authorization => "Authorization",
+            HttpHeader::CacheControl => "Cache-Control",
+            HttpHeader::Connection => "Connection",
+            HttpHeader::ContentEncoding => "Content-Encoding",
+            HttpHeader::ContentLength => "Content-Length",
+            HttpHeader::ContentMd5 => "Content-MD5",
+            HttpHeader::ContentType => "Content-Type",
+            HttpHeader::Cookie => "Cookie",
+            HttpHeader::Date => "Date",
+            HttpHeader::Expect => "Expect",
+            HttpHeader::Forwarded => "Forwarded",
+            HttpHeader::From => "From",
+            HttpHeader::Host => "Host",
+            HttpHeader::Http2Settings => "HTTP2-Settings",
+            HttpHeader::IfMatch => "If-Match",
+            HttpHeader::IfModifiedSince => "If-Modified-Since",
+            HttpHeader::IfNoneMatch => "If-None-Match",
+            HttpHeader::IfRange => "If-Range",
+            HttpHeader::IfUnmodifiedSince => "If-Unmodified-Since",
+            HttpHeader::Location => "Location",
+            HttpHeader::MaxForwards => "Max-Forwards",
+            HttpHeader::Origin => "Origin",
+            HttpHeader::Pragma => "Pragma",
+            HttpHeader::Prefer => "Prefer",
+            HttpHeader::ProxyAuthorization => "Proxy-Authorization",
+            HttpHeader::Range => "Range",
+            HttpHeader::Referer => "Referer",
+            HttpHeader::Server => "Server",
+            HttpHeader::Te => "TE",
+            HttpHeader::Trailer => "Trailer",
+            HttpHeader::TransferEncoding => "Transfer-Encoding",
+            HttpHeader::UserAgent => "User-Agent",
+            HttpHeader::Upgrade => "Upgrade",
+            HttpHeader::Via => "Via",
+            HttpHeader::Warning => "Warning",
+            HttpHeader::Custom(name) => name,
+
+            HttpHeader::PseudoScheme => ":scheme",
+            HttpHeader::PseudoMethod => ":method",
+            HttpHeader::PseudoAuthority => ":authority",
+            HttpHeader::PseudoPath => ":path",
+            HttpHeader::PseudoStatus => ":status",
+            HttpHeader::PseudoProtocol => ":protocol"
         }
     }
 
@@ -216,6 +285,7 @@ impl HttpHeader {
             "accept-encoding" => HttpHeader::AcceptEncoding,
             "accept-language" => HttpHeader::AcceptLanguage,
             "access-control-request-method" => HttpHeader::AccessControlRequestMethod,
+            "allow" => HttpHeader::Allow,
             "authorization" => HttpHeader::Authorization,
             "cache-control" => HttpHeader::CacheControl,
             "connection" => HttpHeader::Connection,
@@ -261,6 +331,15 @@ impl HttpHeader {
         }
     }
 
+    /// The well-known header a custom name spells, such as [HttpHeader::ContentLength] for
+    /// `Custom("content-length")`, or the header itself.
+    pub(crate) fn canonical(self) -> HttpHeader {
+        match self {
+            HttpHeader::Custom(name) => HttpHeader::from_name(&name),
+            header => header
+        }
+    }
+
     pub fn is_pseudo(&self) -> bool {
         match self {
             HttpHeader::PseudoScheme | HttpHeader::PseudoMethod | HttpHeader::PseudoAuthority | HttpHeader::PseudoPath | HttpHeader::PseudoStatus | HttpHeader::PseudoProtocol => true,
@@ -269,6 +348,41 @@ impl HttpHeader {
     }
 }
 
+/// Headers are equal when their names are, ignoring case, so a custom name such as
+/// `Custom("content-length")` stands for the well-known header it spells.
+impl PartialEq for HttpHeader {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (HttpHeader::Custom(_), _) | (_, HttpHeader::Custom(_)) => self.as_str().eq_ignore_ascii_case(other.as_str()),
+            _ => std::mem::discriminant(self) == std::mem::discriminant(other)
+        }
+    }
+}
+
+impl Eq for HttpHeader {}
+
+impl Hash for HttpHeader {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for byte in self.as_str().bytes() {
+            state.write_u8(byte.to_ascii_lowercase());
+        }
+    }
+}
+
+/// Headers are ordered by their lowercase names, which agrees with how they're compared.
+impl Ord for HttpHeader {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        let name = self.as_str().bytes().map(|byte| byte.to_ascii_lowercase());
+        name.cmp(other.as_str().bytes().map(|byte| byte.to_ascii_lowercase()))
+    }
+}
+
+impl PartialOrd for HttpHeader {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub enum ContentType {
     TextPlain,
@@ -334,9 +448,9 @@ impl Display for HttpMethod {
 macro_rules! headers {
     ($($key:ident: $value:expr),*) => {
         {
-            let mut map = std::collections::HashMap::new();
+            let mut map = $crate::request::HeaderMap::new();
             $(
-                map.insert(crate::request::HttpHeader::$key, $value.to_string());
+                map.insert($crate::request::HttpHeader::$key, $value.to_string());
             )*
             map
         }
@@ -0,0 +1,176 @@
+use crate::request::HeaderMap;
+use futures::{Stream, StreamExt};
+use std::fmt::{Debug, Formatter};
+use std::future::Future;
+use std::pin::Pin;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+const READ_CHUNK_SIZE: usize = 16384;
+
+pub type BodyStream = Pin<Box<dyn Stream<Item = std::io::Result<Vec<u8>>> + Send>>;
+pub type BodyReader = Pin<Box<dyn AsyncRead + Send>>;
+pub type BodyTrailers = Pin<Box<dyn Future<Output = HeaderMap> + Send>>;
+
+/// The body of a response, which is either fully in memory or produced while it's being
+/// written out. Bodies of unknown length are sent with chunked transfer-encoding.
+pub struct HttpBody {
+    kind: BodyKind,
+    trailers: Option<BodyTrailers>
+}
+
+enum BodyKind {
+    Full(Option<Vec<u8>>),
+    Stream(BodyStream),
+    Reader(BodyReader)
+}
+
+impl HttpBody {
+    pub fn empty() -> Self {
+        HttpBody::full(Vec::new())
+    }
+
+    pub fn full(bytes: Vec<u8>) -> Self {
+        HttpBody {
+            kind: BodyKind::Full(Some(bytes)),
+            trailers: None
+        }
+    }
+
+    pub fn stream<S, B>(stream: S) -> Self
+    where
+        S: Stream<Item = B> + Send + 'static,
+        B: Into<Vec<u8>>
+    {
+        HttpBody {
+            kind: BodyKind::Stream(Box::pin(stream.map(|chunk| Ok(chunk.into())))),
+            trailers: None
+        }
+    }
+
+    /// Like [HttpBody::stream], but a failing item aborts the response, which closes
+    /// the connection instead of leaving the client with a silently truncated body.
+    pub fn try_stream<S, B, E>(stream: S) -> Self
+    where
+        S: Stream<Item = Result<B, E>> + Send + 'static,
+        B: Into<Vec<u8>>,
+        E: Into<Box<dyn std::error::Error + Send + Sync>>
+    {
+        HttpBody {
+            kind: BodyKind::Stream(Box::pin(stream.map(|chunk| chunk
+                .map(Into::into)
+                .map_err(std::io::Error::other)
+            ))),
+            trailers: None
+        }
+    }
+
+    pub fn reader<R>(reader: R) -> Self where R: AsyncRead + Send + 'static {
+        HttpBody {
+            kind: BodyKind::Reader(Box::pin(reader)),
+            trailers: None
+        }
+    }
+
+    /// Sends the given trailer fields after the body, once it's been entirely written.
+    /// Since trailers require chunked transfer-encoding, this also applies to full bodies.
+    pub fn with_trailers<F>(mut self, trailers: F) -> Self
+    where F: Future<Output = HeaderMap> + Send + 'static {
+        self.trailers = Some(Box::pin(trailers));
+        self
+    }
+
+    /// The length of the body, if it's known before writing it.
+    pub fn len(&self) -> Option<usize> {
+        match &self.kind {
+            BodyKind::Full(bytes) if self.trailers.is_none() => Some(bytes.as_ref().map_or(0, Vec::len)),
+            _ => None
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == Some(0)
+    }
+
+    /// The body contents if it's fully in memory.
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match &self.kind {
+            BodyKind::Full(bytes) => Some(bytes.as_deref().unwrap_or_default()),
+            _ => None
+        }
+    }
+
+    pub fn has_trailers(&self) -> bool {
+        self.trailers.is_some()
+    }
+
+    /// Produces the next piece of the body, returning `None` once it's over.
+    pub async fn next_chunk(&mut self) -> Option<std::io::Result<Vec<u8>>> {
+        match &mut self.kind {
+            BodyKind::Full(bytes) => bytes.take().filter(|bytes| !bytes.is_empty()).map(Ok),
+            BodyKind::Stream(stream) => loop {
+                match stream.next().await {
+                    Some(Ok(chunk)) if chunk.is_empty() => continue,
+                    chunk => break chunk
+                }
+            },
+            BodyKind::Reader(reader) => {
+                let mut chunk = vec![0; READ_CHUNK_SIZE];
+                match reader.read(&mut chunk).await {
+                    Ok(0) => None,
+                    Ok(read) => {
+                        chunk.truncate(read);
+                        Some(Ok(chunk))
+                    },
+                    Err(e) => Some(Err(e))
+                }
+            }
+        }
+    }
+
+    /// Resolves the trailers, which must only be done after the body has been written.
+    pub async fn trailers(&mut self) -> Option<HeaderMap> {
+        match self.trailers.take() {
+            Some(trailers) => Some(trailers.await),
+            None => None
+        }
+    }
+}
+
+impl Default for HttpBody {
+    fn default() -> Self {
+        HttpBody::empty()
+    }
+}
+
+impl From<Vec<u8>> for HttpBody {
+    fn from(bytes: Vec<u8>) -> Self {
+        HttpBody::full(bytes)
+    }
+}
+
+impl From<String> for HttpBody {
+    fn from(string: String) -> Self {
+        HttpBody::full(string.into_bytes())
+    }
+}
+
+impl From<&str> for HttpBody {
+    fn from(string: &str) -> Self {
+        HttpBody::full(string.as_bytes().to_vec())
+    }
+}
+
+impl Debug for HttpBody {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let kind = match &self.kind {
+            BodyKind::Full(_) => "Full",
+            BodyKind::Stream(_) => "Stream",
+            BodyKind::Reader(_) => "Reader"
+        };
+        f.debug_struct("HttpBody")
+            .field("kind", &kind)
+            .field("length", &self.len())
+            .field("trailers", &self.has_trailers())
+            .finish()
+    }
+}
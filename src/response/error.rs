@@ -0,0 +1,58 @@
+use crate::parser::HttpParserError;
+use crate::response::into::IntoResponse;
+use crate::response::{HttpResponse, HttpStatusCode};
+use std::sync::Arc;
+
+/// A request the server refused before it could reach the router, such as a malformed one,
+/// which is answered right before the connection, or the HTTP/2 stream, is closed.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct RequestError {
+    status: HttpStatusCode,
+    reason: &'static str
+}
+
+impl RequestError {
+    pub fn status(&self) -> HttpStatusCode {
+        self.status
+    }
+
+    /// A short description of what was wrong with the request, meant for the client.
+    pub fn reason(&self) -> &'static str {
+        self.reason
+    }
+
+    pub(crate) fn timed_out() -> Self {
+        RequestError::new(HttpStatusCode::RequestTimeout, "The request took too long to arrive")
+    }
+
+    /// The error the client should be told about, or `None` if it's not worth answering,
+    /// such as when the connection was closed.
+    pub(crate) fn from_parser_error(error: &HttpParserError) -> Option<Self> {
+        let (status, reason) = match error {
+            HttpParserError::UriTooLong => (HttpStatusCode::UriTooLong, "The request target is too long"),
+            HttpParserError::HeadersTooLarge => (HttpStatusCode::RequestHeaderFieldsTooLarge, "The request headers are too large"),
+            HttpParserError::BodyTooLarge => (HttpStatusCode::PayloadTooLarge, "The request body is too large"),
+            HttpParserError::InvalidMethod => (HttpStatusCode::NotImplemented, "The request method is not supported"),
+            HttpParserError::UnsupportedTransferEncoding => (HttpStatusCode::NotImplemented, "The transfer coding is not supported"),
+            HttpParserError::InvalidVersion => (HttpStatusCode::HttpVersionNotSupported, "The HTTP version is not supported"),
+            HttpParserError::InvalidRequestLine => (HttpStatusCode::BadRequest, "The request line is malformed"),
+            HttpParserError::InvalidHeader => (HttpStatusCode::BadRequest, "A request header is malformed"),
+            HttpParserError::InvalidContentLength => (HttpStatusCode::BadRequest, "The Content-Length header is invalid"),
+            HttpParserError::AmbiguousBodyLength => (HttpStatusCode::BadRequest, "The request has both Content-Length and Transfer-Encoding"),
+            _ => return None
+        };
+        Some(RequestError::new(status, reason))
+    }
+
+    fn new(status: HttpStatusCode, reason: &'static str) -> Self {
+        RequestError { status, reason }
+    }
+}
+
+/// Builds the responses to [RequestError]s, such as to render them as JSON. An HTTP/1
+/// connection is closed after them either way, while over HTTP/2 only the stream ends.
+pub type ErrorHandler = Arc<dyn Fn(&RequestError) -> HttpResponse + Send + Sync>;
+
+pub(crate) fn default_error_handler() -> ErrorHandler {
+    Arc::new(|error| (error.status(), error.reason()).into_response())
+}
@@ -1,5 +1,6 @@
 use crate::headers;
 use crate::response::{HttpResponse, HttpStatusCode};
+use crate::response::body::HttpBody;
 
 pub trait IntoResponse {
     fn into_response(self) -> HttpResponse;
@@ -18,7 +19,7 @@ impl IntoResponse for &str {
             headers: headers! {
                 ContentType: "text/plain"
             },
-            body: self.as_bytes().to_vec()
+            body: self.into()
         }
     }
 }
@@ -30,7 +31,7 @@ impl IntoResponse for String {
             headers: headers! {
                 ContentType: "text/plain"
             },
-            body: self.into_bytes()
+            body: self.into()
         }
     }
 }
@@ -42,7 +43,7 @@ impl IntoResponse for HttpStatusCode {
             headers: headers! {
                 ContentType: "text/plain"
             },
-            body: vec![]
+            body: HttpBody::empty()
         }
     }
 }
@@ -60,6 +61,18 @@ impl<T> IntoResponse for (HttpStatusCode, T) where T : IntoResponse {
 }
 
 impl IntoResponse for Vec<u8> {
+    fn into_response(self) -> HttpResponse {
+        HttpResponse {
+            status_code: HttpStatusCode::OK,
+            headers: headers! {
+                ContentType: "application/octet-stream"
+            },
+            body: self.into()
+        }
+    }
+}
+
+impl IntoResponse for HttpBody {
     fn into_response(self) -> HttpResponse {
         HttpResponse {
             status_code: HttpStatusCode::OK,
@@ -69,4 +82,4 @@ impl IntoResponse for Vec<u8> {
             body: self
         }
     }
-}
\ No newline at end of file
+}
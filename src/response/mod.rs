@@ -1,21 +1,24 @@
 pub mod into;
+pub mod body;
+pub mod error;
 
 use std::fmt::Display;
-use crate::request::HttpHeaderMap;
+use crate::request::HeaderMap;
+use crate::response::body::HttpBody;
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct HttpResponse {
     pub status_code: HttpStatusCode,
-    pub headers: HttpHeaderMap,
-    pub body: Vec<u8>
+    pub headers: HeaderMap,
+    pub body: HttpBody
 }
 
 impl HttpResponse {
     pub fn empty(status: HttpStatusCode) -> HttpResponse {
         HttpResponse {
             status_code: HttpStatusCode::from(status),
-            headers: HttpHeaderMap::new(),
-            body: Vec::new()
+            headers: HeaderMap::new(),
+            body: HttpBody::empty()
         }
     }
 }
@@ -106,6 +109,12 @@ impl HttpStatusCode {
         }
     }
 
+    /// Whether responses with this status can have a body, which informational ones, `204 No
+    /// Content` and `304 Not Modified` can't.
+    pub fn allows_body(&self) -> bool {
+        !self.is_informational() && !matches!(self, HttpStatusCode::NoContent | HttpStatusCode::NotModified)
+    }
+
     pub fn is_success(&self) -> bool {
         match self {
             HttpStatusCode::OK => true,
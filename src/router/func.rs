@@ -16,11 +16,8 @@ pub struct CallbackRouteHandler {
 
 #[async_trait]
 impl RouteHandler for CallbackRouteHandler {
-    fn handles_method(&self, method: HttpMethod) -> bool {
-        match &self.method {
-            Some(m) => *m == method,
-            None => true
-        }
+    fn method(&self) -> Option<HttpMethod> {
+        self.method
     }
 
     async fn handle(&self, request: HttpRequest) -> HttpResponse {
@@ -86,6 +83,24 @@ pub fn handle_function_failure(err: ExtractorError) -> HttpResponse {
         },
         ExtractorError::MissingQueryParameter => {
             (HttpStatusCode::BadRequest, "The request did not have the expected query parameter").into_response()
+        },
+        ExtractorError::MissingPeerCertificate => {
+            (HttpStatusCode::Unauthorized, "A client certificate is required").into_response()
+        },
+        ExtractorError::MissingPeerCredentials => {
+            (HttpStatusCode::Forbidden, "This route is only available over a Unix socket").into_response()
+        },
+        ExtractorError::MissingListener => {
+            (HttpStatusCode::InternalServerError, "altaria: This route handler expected a listener, but the connection wasn't accepted by one").into_response()
+        },
+        ExtractorError::MissingProxyHeader => {
+            (HttpStatusCode::InternalServerError, "altaria: This route handler expected a PROXY protocol header, but the listener doesn't have it enabled").into_response()
+        },
+        ExtractorError::RequestTimeout => {
+            (HttpStatusCode::RequestTimeout, "The request body took too long to arrive").into_response()
+        },
+        ExtractorError::BodyTooLarge => {
+            (HttpStatusCode::PayloadTooLarge, "The request body is too large").into_response()
         }
     }
 }
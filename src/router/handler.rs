@@ -4,8 +4,10 @@ use crate::response::HttpResponse;
 
 #[async_trait]
 pub trait RouteHandler {
-    fn handles_method(&self, _method: crate::request::HttpMethod) -> bool {
-        true
+    /// The method this handler answers, or `None` to answer every method that has no
+    /// handler of its own on the same path.
+    fn method(&self) -> Option<crate::request::HttpMethod> {
+        None
     }
 
     async fn handle(&self, request: HttpRequest) -> HttpResponse;
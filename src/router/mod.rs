@@ -1,5 +1,6 @@
 pub mod handler;
 mod tree;
+mod pattern;
 pub mod func;
 pub mod flow;
 #[cfg(feature = "macros")]
@@ -7,11 +8,11 @@ pub mod macros;
 
 use crate::extractor::state::{Resource, ResourceMap};
 use crate::router::flow::RequestFlow;
-use crate::request::HttpRequest;
-use crate::response::HttpResponse;
+use crate::request::{HttpHeader, HttpRequest};
+use crate::response::{HttpResponse, HttpStatusCode};
 use crate::router::func::FunctionRouteHandler;
 use crate::router::handler::RouteHandler;
-use crate::router::tree::RouteNode;
+use crate::router::tree::{MethodMatch, RouteNode};
 use async_trait::async_trait;
 use std::any::TypeId;
 use std::collections::HashMap;
@@ -47,6 +48,13 @@ pub struct Router {
     middlewares: MiddlewareChain
 }
 
+/// The resources and middleware of a router nested into another, which only apply to the
+/// routes that came from it.
+pub(crate) struct RouterScope {
+    resources: ResourceMap,
+    middlewares: MiddlewareChain
+}
+
 impl Router {
     pub fn new() -> Self {
         Router {
@@ -55,6 +63,26 @@ impl Router {
             middlewares: MiddlewareChain::new()
         }
     }
+
+    /// Mounts the routes of another router under the prefix, such as `/api/v1`. Its resources
+    /// and middleware only apply to those routes, on top of the ones of this router, and its
+    /// resources take precedence over the ones of the same type here. The prefix may have
+    /// dynamic segments, whose values are available to the nested handlers.
+    pub fn nest(mut self, prefix: &str, router: Router) -> Self {
+        let scope = RouterScope {
+            resources: router.resources,
+            middlewares: router.middlewares
+        };
+        self.root.mount(prefix, &router.root, Arc::new(scope));
+        self
+    }
+
+    /// Builds a router for the routes under the prefix and nests it, see [Router::nest].
+    pub fn group<F>(self, prefix: &str, build: F) -> Self where
+        F: FnOnce(Router) -> Router
+    {
+        self.nest(prefix, build(Router::new()))
+    }
 }
 
 #[async_trait]
@@ -62,7 +90,7 @@ impl HttpRouter for Router {
     fn insert_handler<Handler>(mut self, path: &str, handler: Handler) -> Self where
         Handler : RouteHandler + Send + Sync + 'static
     {
-        self.root.insert(path, Box::new(handler));
+        self.root.insert(path, Arc::new(handler));
         self
     }
 
@@ -101,27 +129,47 @@ impl HttpRouter for Router {
         self
     }
 
+    /// Answers with `405 Method Not Allowed` if the path has no handler for the method, and
+    /// `OPTIONS` and `HEAD` requests automatically if it doesn't handle them itself. The body
+    /// of responses to `HEAD` requests is left out when they're written, not here.
     async fn route(&self, mut request: HttpRequest) -> Option<HttpResponse> {
-        let route = self.root.find(&request.path)?;
-        let handler = route.handler;
-        if !handler.handles_method(request.method) {
-            return None
-        }
-        let flow = Arc::new(RequestFlow::new(self.clone_resources()));
+        let route = self.root.find(&request.path, request.method)?;
+        let matched = match route.handlers.resolve(request.method) {
+            MethodMatch::Handler(matched) | MethodMatch::HeadAsGet(matched) => matched,
+            MethodMatch::Options => return Some(self.allow_response(HttpStatusCode::NoContent, &request.path)),
+            MethodMatch::NotAllowed => return Some(self.allow_response(HttpStatusCode::MethodNotAllowed, &request.path))
+        };
+        let flow = Arc::new(RequestFlow::new(self.clone_resources(&matched.scopes)));
         request.set_flow(flow.clone());
         self.middlewares.apply_before(&mut request);
+        for scope in &matched.scopes {
+            scope.middlewares.apply_before(&mut request);
+        }
 
-        request.set_route_path(route.into_path_values());
-        let mut response = handler.handle(request).await;
+        request.set_route_path(route.into_path_values(matched));
+        let mut response = matched.handler.handle(request).await;
 
+        for scope in matched.scopes.iter().rev() {
+            scope.middlewares.apply_after(flow.clone(), &mut response);
+        }
         self.middlewares.apply_after(flow, &mut response);
         Some(response)
     }
 }
 
 impl Router {
-    fn clone_resources(&self) -> ResourceMap {
-        self.resources.iter()
+    fn allow_response(&self, status: HttpStatusCode, path: &str) -> HttpResponse {
+        let mut response = HttpResponse::empty(status);
+        response.headers.insert(HttpHeader::Allow, self.root.allow(path));
+        response
+    }
+
+    /// The resources of this router along with the ones of the routers the route was nested
+    /// from, where the innermost ones win.
+    fn clone_resources(&self, scopes: &[Arc<RouterScope>]) -> ResourceMap {
+        std::iter::once(&self.resources)
+            .chain(scopes.iter().map(|scope| &scope.resources))
+            .flatten()
             .map(|(type_id, resource)| (*type_id, resource.clone_box()))
             .collect()
     }
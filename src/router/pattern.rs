@@ -0,0 +1,140 @@
+use regex::Regex;
+use std::fmt::{Debug, Formatter};
+
+/// A segment of a route pattern, such as `users`, `{id}`, `{id:u64}`, `{page?}` or `{*path}`.
+pub(crate) enum PatternSegment<'a> {
+    Static(&'a str),
+    Dynamic { name: &'a str, optional: bool, constraint: ParamConstraint },
+    Wildcard { name: &'a str, optional: bool }
+}
+
+impl<'a> PatternSegment<'a> {
+    /// Parses a segment, where parameters are written as `{name}`, `{*name}` for catch-alls
+    /// and `{name?}` for optional ones, followed by `:constraint` when they have one.
+    pub(crate) fn parse(segment: &'a str) -> Self {
+        let Some(inner) = segment.strip_prefix('{').and_then(|segment| segment.strip_suffix('}')) else {
+            return PatternSegment::Static(segment);
+        };
+        let (name, constraint) = match inner.split_once(':') {
+            Some((name, constraint)) => (name, Some(constraint)),
+            None => (inner, None)
+        };
+        let (name, optional) = match name.strip_suffix('?') {
+            Some(name) => (name, true),
+            None => (name, false)
+        };
+        match (name.strip_prefix('*'), constraint) {
+            (Some(name), None) => PatternSegment::Wildcard { name, optional },
+            (Some(_), Some(_)) => panic!("The catch-all segment {segment} can't have a constraint"),
+            (None, constraint) => PatternSegment::Dynamic {
+                name,
+                optional,
+                constraint: constraint.map_or(ParamConstraint::Any, |constraint| ParamConstraint::parse(segment, constraint))
+            }
+        }
+    }
+}
+
+/// What a dynamic segment accepts, checked while the route is being matched.
+#[derive(Clone)]
+pub(crate) enum ParamConstraint {
+    Any,
+    /// One of the built-in types, such as `u64` or `uuid`
+    Builtin(&'static str, ConstraintCheck),
+    /// A regular expression the whole segment must match
+    Pattern(Regex)
+}
+
+type ConstraintCheck = fn(&str) -> bool;
+
+const BUILTIN_CONSTRAINTS: &[(&str, ConstraintCheck)] = &[
+    ("u8", |value| value.parse::<u8>().is_ok()),
+    ("u16", |value| value.parse::<u16>().is_ok()),
+    ("u32", |value| value.parse::<u32>().is_ok()),
+    ("u64", |value| value.parse::<u64>().is_ok()),
+    ("u128", |value| value.parse::<u128>().is_ok()),
+    ("usize", |value| value.parse::<usize>().is_ok()),
+    ("i8", |value| value.parse::<i8>().is_ok()),
+    ("i16", |value| value.parse::<i16>().is_ok()),
+    ("i32", |value| value.parse::<i32>().is_ok()),
+    ("i64", |value| value.parse::<i64>().is_ok()),
+    ("i128", |value| value.parse::<i128>().is_ok()),
+    ("isize", |value| value.parse::<isize>().is_ok()),
+    ("f32", |value| value.parse::<f32>().is_ok()),
+    ("f64", |value| value.parse::<f64>().is_ok()),
+    ("bool", |value| value.parse::<bool>().is_ok()),
+    ("uuid", is_uuid)
+];
+
+impl ParamConstraint {
+    /// A built-in type if there's one by that name, or a regular expression otherwise.
+    fn parse(segment: &str, constraint: &str) -> Self {
+        if let Some((name, check)) = BUILTIN_CONSTRAINTS.iter().find(|(name, _)| *name == constraint) {
+            return ParamConstraint::Builtin(name, *check);
+        }
+        match Regex::new(&format!("^(?:{constraint})$")) {
+            Ok(regex) => ParamConstraint::Pattern(regex),
+            Err(error) => panic!("Invalid constraint in the route segment {segment}: {error}")
+        }
+    }
+
+    pub(crate) fn accepts(&self, value: &str) -> bool {
+        match self {
+            ParamConstraint::Any => true,
+            ParamConstraint::Builtin(_, check) => check(value),
+            ParamConstraint::Pattern(regex) => regex.is_match(value)
+        }
+    }
+
+    pub(crate) fn is_any(&self) -> bool {
+        matches!(self, ParamConstraint::Any)
+    }
+
+    /// The constraint as written in the route pattern, which tells equal constraints apart.
+    fn source(&self) -> &str {
+        match self {
+            ParamConstraint::Any => "",
+            ParamConstraint::Builtin(name, _) => name,
+            ParamConstraint::Pattern(regex) => regex.as_str()
+        }
+    }
+}
+
+impl PartialEq for ParamConstraint {
+    fn eq(&self, other: &Self) -> bool {
+        self.source() == other.source()
+    }
+}
+
+impl Debug for ParamConstraint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParamConstraint::Any => write!(f, "Any"),
+            _ => write!(f, "{}", self.source())
+        }
+    }
+}
+
+/// Whether the value is a UUID in its hyphenated form, in any case.
+fn is_uuid(value: &str) -> bool {
+    let groups: Vec<&str> = value.split('-').collect();
+    groups.len() == 5
+        && groups.iter().zip([8, 4, 4, 4, 12]).all(|(group, length)| {
+            group.len() == length && group.bytes().all(|byte| byte.is_ascii_hexdigit())
+        })
+}
+
+/// Leaves out the query part of a route pattern, such as `?sec={secret}`, which the
+/// endpoint macros use to name query parameters.
+pub(crate) fn strip_query_pattern(path: &str) -> &str {
+    let mut depth = 0;
+    for (index, char) in path.char_indices() {
+        match char {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            '?' if depth == 0 => return &path[..index],
+            _ => {}
+        }
+    }
+    path
+}
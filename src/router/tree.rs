@@ -1,84 +1,306 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::{Debug, Formatter};
-use crate::request::RoutePathValues;
+use std::sync::Arc;
+use crate::request::{HttpMethod, RoutePathValues};
 use crate::router::handler::RouteHandler;
+use crate::router::RouterScope;
+use crate::router::pattern::{strip_query_pattern, ParamConstraint, PatternSegment};
+
+type SharedRouteHandler = Arc<dyn RouteHandler + Send + Sync>;
 
 pub struct RouteNode {
-    handler: Option<Box<dyn RouteHandler + Send + Sync>>,
-    dynamic_child: Option<Box<RouteNode>>,
-    dynamic_id: Option<String>,
+    handlers: MethodHandlers,
+    /// Tried in order, with the constrained ones before the one that accepts anything
+    dynamic_children: Vec<(ParamConstraint, RouteNode)>,
+    /// Matches the rest of the path, as long as there's at least one segment left
+    wildcard_child: Option<Box<RouteNode>>,
     children: HashMap<String, RouteNode>,
 }
 
 impl RouteNode {
     pub fn new() -> Self {
         Self {
-            handler: None,
-            dynamic_child: None,
-            dynamic_id: None,
+            handlers: MethodHandlers::default(),
+            dynamic_children: Vec::new(),
+            wildcard_child: None,
             children: HashMap::new(),
         }
     }
 
-    pub fn insert(&mut self, path: &str, handler: Box<dyn RouteHandler + Send + Sync>) {
-        let path_segments: Vec<&str> = separate_path_segments(path);
-        let mut current = self;
+    /// Adds a handler for the path. Optional segments (`{name?}`) are registered both with
+    /// and without them, a catch-all segment (`{*name}`) must come last, and dynamic ones
+    /// may be constrained, such as `{id:u64}` or `{slug:[a-z-]+}`. A catch-all needs at least
+    /// one segment to match, so `/files/{*path}` doesn't match `/files`, while `/files/{*path?}`
+    /// does. Panics if the path already has a handler for the same method, or if the pattern
+    /// is malformed.
+    pub fn insert(&mut self, path: &str, handler: SharedRouteHandler) {
+        let segments: Vec<PatternSegment> = separate_path_segments(strip_query_pattern(path))
+            .into_iter()
+            .map(PatternSegment::parse)
+            .collect();
+        let wildcard = segments.iter().position(|segment| matches!(segment, PatternSegment::Wildcard { .. }));
+        if wildcard.is_some_and(|index| index != segments.len() - 1) {
+            panic!("The catch-all segment of the route {path} must be the last one");
+        }
+        self.insert_segments(path, &segments, &handler, Vec::new());
+    }
+
+    fn insert_segments(&mut self, path: &str, segments: &[PatternSegment], handler: &SharedRouteHandler, params: Vec<String>) {
+        let Some((segment, rest)) = segments.split_first() else {
+            let method = handler.method().map_or("every method".to_string(), |method| method.to_string());
+            if !self.handlers.insert(Route { handler: handler.clone(), params, scopes: Vec::new() }) {
+                panic!("The route {path} already has a handler for {method}");
+            }
+            return;
+        };
 
-        for segment in path_segments {
-            if segment.starts_with('{') && segment.ends_with('}') {
-                if current.dynamic_child.is_none() {
-                    current.dynamic_child = Some(Box::new(RouteNode::new()))
-                }
-                let child = current.dynamic_child.as_mut().unwrap();
-                child.dynamic_id = Some(segment[1..segment.len()-1].to_string());
-                current = child
-            } else {
-                current = current.children.entry(segment.to_string()).or_insert_with(RouteNode::new)
+        let (child, name, optional) = match segment {
+            PatternSegment::Static(segment) => {
+                let child = self.children.entry(segment.to_string()).or_insert_with(RouteNode::new);
+                (child, None, false)
+            },
+            PatternSegment::Dynamic { name, optional, constraint } => (self.dynamic_child(constraint), Some(name), *optional),
+            PatternSegment::Wildcard { name, optional } => {
+                let child = self.wildcard_child.get_or_insert_with(|| Box::new(RouteNode::new()));
+                (&mut **child, Some(name), *optional)
             }
+        };
+        let mut child_params = params.clone();
+        child_params.extend(name.map(|name| name.to_string()));
+        child.insert_segments(path, rest, handler, child_params);
+        if optional {
+            self.insert_segments(path, rest, handler, params);
         }
-        current.handler = Some(handler);
     }
 
-    pub fn find(&self, path: &str) -> Option<RouteHandlerPath> {
-        let mut segments: Vec<&str> = separate_path_segments(path);
-        let mut current = self;
-        let mut params: HashMap<String, String> = HashMap::new();
-        let last_path = *segments.last().unwrap();
-        let mut queries = HashMap::new();
-        if let Some(index) = last_path.find('?') {
-            let (path, query) = last_path.split_at(index);
-            segments.pop();
-            segments.push(path);
-            if index != last_path.len() - 1 {
-                for query in query[1..].split('&') {
-                    let mut parts = query.split('=');
-                    let key = parts.next().unwrap();
-                    let value = parts.next().unwrap();
-                    queries.insert(key.to_string(), value.to_string());
-                }
+    /// Adds the routes of another tree under the prefix, in the scope of the router they
+    /// came from. Panics if the prefix has a catch-all segment, or if a route ends up with
+    /// two handlers for the same method.
+    pub(crate) fn mount(&mut self, prefix: &str, tree: &RouteNode, scope: Arc<RouterScope>) {
+        let segments: Vec<PatternSegment> = separate_path_segments(prefix)
+            .into_iter()
+            .map(PatternSegment::parse)
+            .collect();
+        if segments.iter().any(|segment| matches!(segment, PatternSegment::Wildcard { .. })) {
+            panic!("The prefix {prefix} can't have a catch-all segment");
+        }
+        self.mount_segments(prefix, &segments, tree, &scope, Vec::new());
+    }
+
+    fn mount_segments(&mut self, prefix: &str, segments: &[PatternSegment], tree: &RouteNode, scope: &Arc<RouterScope>, params: Vec<String>) {
+        let Some((segment, rest)) = segments.split_first() else {
+            let mount = |route: &Route| Route {
+                handler: route.handler.clone(),
+                params: params.iter().chain(&route.params).cloned().collect(),
+                scopes: std::iter::once(scope.clone()).chain(route.scopes.iter().cloned()).collect()
+            };
+            self.merge(prefix, tree, &mount);
+            return;
+        };
+
+        let (child, name, optional) = match segment {
+            PatternSegment::Static(segment) => {
+                let child = self.children.entry(segment.to_string()).or_insert_with(RouteNode::new);
+                (child, None, false)
+            },
+            PatternSegment::Dynamic { name, optional, constraint } => (self.dynamic_child(constraint), Some(name), *optional),
+            PatternSegment::Wildcard { .. } => unreachable!("Prefixes are checked for catch-all segments")
+        };
+        let mut child_params = params.clone();
+        child_params.extend(name.map(|name| name.to_string()));
+        child.mount_segments(prefix, rest, tree, scope, child_params);
+        if optional {
+            self.mount_segments(prefix, rest, tree, scope, params);
+        }
+    }
+
+    fn merge(&mut self, prefix: &str, tree: &RouteNode, mount: &dyn Fn(&Route) -> Route) {
+        for route in tree.handlers.routes() {
+            if !self.handlers.insert(mount(route)) {
+                let method = route.handler.method().map_or("every method".to_string(), |method| method.to_string());
+                panic!("A route mounted under {prefix} already has a handler for {method}");
             }
         }
+        for (segment, child) in &tree.children {
+            self.children.entry(segment.clone()).or_insert_with(RouteNode::new).merge(prefix, child, mount);
+        }
+        for (constraint, child) in &tree.dynamic_children {
+            self.dynamic_child(constraint).merge(prefix, child, mount);
+        }
+        if let Some(wildcard) = &tree.wildcard_child {
+            self.wildcard_child.get_or_insert_with(|| Box::new(RouteNode::new())).merge(prefix, wildcard, mount);
+        }
+    }
 
-        for segment in segments {
-            if let Some(child) = current.children.get(segment) {
-                current = child;
-            } else if let Some(dynamic) = &current.dynamic_child {
-                params.insert(
-                    dynamic.dynamic_id.clone().expect("Dynamic child doesn't have a dynamic id"),
-                    segment.to_string()
-                );
-                current = dynamic
-            } else {
-                return None
+    /// The child for the constraint, shared by every route that constrains the segment the
+    /// same way, whatever they name it.
+    fn dynamic_child(&mut self, constraint: &ParamConstraint) -> &mut RouteNode {
+        let index = match self.dynamic_children.iter().position(|(existing, _)| existing == constraint) {
+            Some(index) => index,
+            None => {
+                let constraint = constraint.clone();
+                // Constrained children go before the unconstrained one, which would match anything
+                let any = self.dynamic_children.iter().position(|(existing, _)| existing.is_any());
+                let index = match any {
+                    Some(any) if !constraint.is_any() => any,
+                    _ => self.dynamic_children.len()
+                };
+                self.dynamic_children.insert(index, (constraint, RouteNode::new()));
+                index
             }
+        };
+        &mut self.dynamic_children[index].1
+    }
+
+    /// The handlers of the route the path matches. Static segments are preferred over
+    /// dynamic ones, constrained dynamic ones over the rest and those over catch-alls,
+    /// falling back to the next option whenever the preferred branch doesn't lead to a route
+    /// that answers the method. If none does, it's the first route the path matches.
+    pub fn find(&self, path: &str, method: HttpMethod) -> Option<RouteHandlerPath<'_>> {
+        let (path, query) = path.split_once('?').unwrap_or((path, ""));
+        let segments: Vec<&str> = separate_path_segments(path);
+        let mut queries = HashMap::new();
+        for query in query.split('&').filter(|query| !query.is_empty()) {
+            let mut parts = query.split('=');
+            let key = parts.next().unwrap();
+            let value = parts.next().unwrap_or("");
+            queries.insert(key.to_string(), value.to_string());
         }
 
-        current.handler.as_ref().map(move |handler| RouteHandlerPath {
-            handler,
-            params,
+        let mut values = Vec::new();
+        let answers = |handlers: &MethodHandlers| !matches!(handlers.resolve(method), MethodMatch::NotAllowed);
+        let current = match self.find_node(&segments, &mut values, &answers) {
+            Some(current) => current,
+            None => self.find_node(&segments, &mut values, &|handlers| !handlers.is_empty())?
+        };
+        Some(RouteHandlerPath {
+            handlers: &current.handlers,
+            values,
             queries
         })
     }
+
+    /// The value of the `Allow` header for the path, listing the methods of every route it
+    /// matches, since a request is dispatched to whichever of them answers its method. A
+    /// route with a handler for any method allows all of them.
+    pub(crate) fn allow(&self, path: &str) -> String {
+        let path = path.split_once('?').map_or(path, |(path, _)| path);
+        let mut matched = Vec::new();
+        self.collect_matches(&separate_path_segments(path), &mut matched);
+        let mut methods: Vec<HttpMethod> = if matched.iter().any(|handlers| handlers.any.is_some()) {
+            HttpMethod::ALL.to_vec()
+        } else {
+            matched.iter().flat_map(|handlers| handlers.by_method.keys().copied()).collect()
+        };
+        if methods.contains(&HttpMethod::GET) {
+            methods.push(HttpMethod::HEAD);
+        }
+        methods.push(HttpMethod::OPTIONS);
+        methods.sort();
+        methods.dedup();
+        methods.iter().map(HttpMethod::to_string).collect::<Vec<_>>().join(", ")
+    }
+
+    fn collect_matches<'a>(&'a self, segments: &[&str], matched: &mut Vec<&'a MethodHandlers>) {
+        let Some((segment, rest)) = segments.split_first() else {
+            if !self.handlers.is_empty() {
+                matched.push(&self.handlers);
+            }
+            return;
+        };
+        if let Some(child) = self.children.get(*segment) {
+            child.collect_matches(rest, matched);
+        }
+        for (constraint, dynamic) in &self.dynamic_children {
+            if constraint.accepts(segment) {
+                dynamic.collect_matches(rest, matched);
+            }
+        }
+        if let Some(wildcard) = self.wildcard_child.as_ref().filter(|wildcard| !wildcard.handlers.is_empty()) {
+            matched.push(&wildcard.handlers);
+        }
+    }
+
+    /// Collects the values of the dynamic segments along the way, in order, since their
+    /// names depend on which route ends up handling the request.
+    fn find_node<'a>(&'a self, segments: &[&str], values: &mut Vec<String>, accept: &dyn Fn(&MethodHandlers) -> bool) -> Option<&'a RouteNode> {
+        let Some((segment, rest)) = segments.split_first() else {
+            return accept(&self.handlers).then_some(self);
+        };
+        if let Some(found) = self.children.get(*segment).and_then(|child| child.find_node(rest, values, accept)) {
+            return Some(found);
+        }
+        for (constraint, dynamic) in &self.dynamic_children {
+            if !constraint.accepts(segment) {
+                continue;
+            }
+            values.push(segment.to_string());
+            if let Some(found) = dynamic.find_node(rest, values, accept) {
+                return Some(found);
+            }
+            values.pop();
+        }
+        let wildcard = self.wildcard_child.as_ref().filter(|wildcard| accept(&wildcard.handlers))?;
+        values.push(segments.join("/"));
+        Some(wildcard)
+    }
+}
+
+/// A handler along with the names its route gives to the dynamic segments, in order.
+pub(crate) struct Route {
+    pub handler: SharedRouteHandler,
+    params: Vec<String>,
+    /// The routers it was nested from, from the outermost to the innermost
+    pub scopes: Vec<Arc<RouterScope>>
+}
+
+/// The handlers of a single route, one per method.
+#[derive(Default)]
+pub(crate) struct MethodHandlers {
+    by_method: BTreeMap<HttpMethod, Route>,
+    /// Answers the methods that don't have a handler of their own
+    any: Option<Route>
+}
+
+/// How a route answers a method.
+pub(crate) enum MethodMatch<'a> {
+    Handler(&'a Route),
+    /// A `HEAD` request answered by the `GET` handler, whose body must not be sent
+    HeadAsGet(&'a Route),
+    /// An `OPTIONS` request the route has no handler for
+    Options,
+    NotAllowed
+}
+
+impl MethodHandlers {
+    /// Returns false if there's already a handler for the same method.
+    fn insert(&mut self, route: Route) -> bool {
+        match route.handler.method() {
+            Some(method) if self.by_method.contains_key(&method) => false,
+            Some(method) => self.by_method.insert(method, route).is_none(),
+            None if self.any.is_some() => false,
+            None => self.any.replace(route).is_none()
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.by_method.is_empty() && self.any.is_none()
+    }
+
+    fn routes(&self) -> impl Iterator<Item = &Route> {
+        self.by_method.values().chain(&self.any)
+    }
+
+    pub(crate) fn resolve(&self, method: HttpMethod) -> MethodMatch<'_> {
+        if let Some(route) = self.by_method.get(&method).or(self.any.as_ref()) {
+            return MethodMatch::Handler(route);
+        }
+        match (method, self.by_method.get(&HttpMethod::GET)) {
+            (HttpMethod::HEAD, Some(route)) => MethodMatch::HeadAsGet(route),
+            (HttpMethod::OPTIONS, _) => MethodMatch::Options,
+            _ => MethodMatch::NotAllowed
+        }
+    }
 }
 
 fn separate_path_segments(path: &str) -> Vec<&str> {
@@ -87,15 +309,16 @@ fn separate_path_segments(path: &str) -> Vec<&str> {
 
 #[derive(Clone)]
 pub(crate) struct RouteHandlerPath<'a> {
-    pub handler: &'a Box<dyn RouteHandler + Send + Sync>,
-    pub params: HashMap<String, String>,
+    pub handlers: &'a MethodHandlers,
+    /// The values of the dynamic segments, named by the route that handles the request
+    pub values: Vec<String>,
     pub queries: HashMap<String, String>,
 }
 
 impl RouteHandlerPath<'_> {
-    pub(crate) fn into_path_values(self) -> RoutePathValues {
+    pub(crate) fn into_path_values(self, route: &Route) -> RoutePathValues {
         RoutePathValues {
-            params: self.params,
+            params: route.params.iter().cloned().zip(self.values).collect(),
             queries: self.queries,
         }
     }
@@ -104,8 +327,8 @@ impl RouteHandlerPath<'_> {
 impl Debug for RouteNode {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("RouteNode")
-            .field("dynamic_child", &self.dynamic_child)
-            .field("dynamic_id", &self.dynamic_id)
+            .field("dynamic_children", &self.dynamic_children)
+            .field("wildcard_child", &self.wildcard_child)
             .field("children", &self.children)
             .finish()
     }
@@ -114,8 +337,166 @@ impl Debug for RouteNode {
 impl Debug for RouteHandlerPath<'_> {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("RouteHandlerPath")
-            .field("params", &self.params)
+            .field("values", &self.values)
             .field("queries", &self.queries)
             .finish()
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request::HttpRequest;
+    use crate::response::into::IntoResponse;
+    use crate::response::HttpResponse;
+    use async_trait::async_trait;
+    use HttpMethod::{DELETE, GET, HEAD, OPTIONS, POST};
+
+    struct Stub(Option<HttpMethod>);
+
+    #[async_trait]
+    impl RouteHandler for Stub {
+        fn method(&self) -> Option<HttpMethod> {
+            self.0
+        }
+
+        async fn handle(&self, _request: HttpRequest) -> HttpResponse {
+            "stub".into_response()
+        }
+    }
+
+    /// A tree that remembers which pattern each handler was registered with.
+    struct Routes {
+        tree: RouteNode,
+        patterns: Vec<(&'static str, SharedRouteHandler)>
+    }
+
+    impl Routes {
+        fn new(routes: &[(Option<HttpMethod>, &'static str)]) -> Self {
+            let mut tree = RouteNode::new();
+            let mut patterns = Vec::new();
+            for (method, pattern) in routes {
+                let handler: SharedRouteHandler = Arc::new(Stub(*method));
+                tree.insert(pattern, handler.clone());
+                patterns.push((*pattern, handler));
+            }
+            Routes { tree, patterns }
+        }
+
+        /// The pattern of the route that handles the request, along with its parameters.
+        fn dispatch(&self, method: HttpMethod, path: &str) -> Option<(&'static str, Vec<(String, String)>)> {
+            let found = self.tree.find(path, method)?;
+            let route = match found.handlers.resolve(method) {
+                MethodMatch::Handler(route) | MethodMatch::HeadAsGet(route) => route,
+                MethodMatch::Options | MethodMatch::NotAllowed => return None
+            };
+            let pattern = self.patterns.iter().find(|(_, handler)| Arc::ptr_eq(handler, &route.handler))?.0;
+            let mut params: Vec<(String, String)> = found.into_path_values(route).params.into_iter().collect();
+            params.sort();
+            Some((pattern, params))
+        }
+
+        fn pattern(&self, method: HttpMethod, path: &str) -> Option<&'static str> {
+            self.dispatch(method, path).map(|(pattern, _)| pattern)
+        }
+    }
+
+    fn params(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn constrained_params_dispatch_by_shape() {
+        let routes = Routes::new(&[
+            (Some(GET), "/users/{name}"),
+            (Some(GET), "/users/{id:u64}"),
+            (Some(GET), "/items/{uuid:uuid}"),
+            (Some(GET), "/blog/{slug:[a-z-]+}")
+        ]);
+        assert_eq!(routes.dispatch(GET, "/users/42"), Some(("/users/{id:u64}", params(&[("id", "42")]))));
+        assert_eq!(routes.dispatch(GET, "/users/bob"), Some(("/users/{name}", params(&[("name", "bob")]))));
+        assert_eq!(routes.pattern(GET, "/users/-1"), Some("/users/{name}"));
+        assert_eq!(routes.pattern(GET, "/items/123e4567-e89b-12d3-a456-426614174000"), Some("/items/{uuid:uuid}"));
+        assert_eq!(routes.pattern(GET, "/items/123e4567"), None);
+        assert_eq!(routes.pattern(GET, "/blog/hello-world"), Some("/blog/{slug:[a-z-]+}"));
+        assert_eq!(routes.pattern(GET, "/blog/Hello"), None);
+    }
+
+    #[test]
+    fn routes_sharing_a_segment_keep_their_own_param_names() {
+        let routes = Routes::new(&[(Some(GET), "/users/{id}"), (Some(GET), "/users/{name}/posts")]);
+        assert_eq!(routes.dispatch(GET, "/users/7"), Some(("/users/{id}", params(&[("id", "7")]))));
+        assert_eq!(routes.dispatch(GET, "/users/ann/posts"), Some(("/users/{name}/posts", params(&[("name", "ann")]))));
+    }
+
+    #[test]
+    fn method_falls_back_to_another_matching_route() {
+        let routes = Routes::new(&[(Some(GET), "/users/{id:u64}"), (Some(POST), "/users/{name}")]);
+        assert_eq!(routes.dispatch(POST, "/users/42"), Some(("/users/{name}", params(&[("name", "42")]))));
+        assert_eq!(routes.pattern(HEAD, "/users/42"), Some("/users/{id:u64}"));
+        assert_eq!(routes.pattern(DELETE, "/users/42"), None);
+    }
+
+    #[test]
+    fn allow_lists_the_methods_of_every_matching_route() {
+        let routes = Routes::new(&[(Some(GET), "/users/{id:u64}"), (Some(POST), "/users/{name}")]);
+        assert_eq!(routes.tree.allow("/users/42"), "GET, POST, OPTIONS, HEAD");
+        assert_eq!(routes.tree.allow("/users/bob?page=2"), "POST, OPTIONS");
+        assert!(matches!(routes.tree.find("/users/42", OPTIONS).unwrap().handlers.resolve(OPTIONS), MethodMatch::Options));
+    }
+
+    #[test]
+    fn route_for_any_method_is_never_refused() {
+        let routes = Routes::new(&[(Some(GET), "/users/{id:u64}"), (None, "/users/{name}")]);
+        assert_eq!(routes.pattern(DELETE, "/users/42"), Some("/users/{name}"));
+        assert_eq!(routes.pattern(GET, "/users/42"), Some("/users/{id:u64}"));
+        assert_eq!(routes.tree.allow("/users/42"), "GET, POST, PUT, DELETE, OPTIONS, HEAD, PATCH, TRACE");
+        assert_eq!(routes.tree.allow("/posts"), "OPTIONS");
+    }
+
+    #[test]
+    fn static_segments_win_over_dynamic_ones_and_those_over_catch_alls() {
+        let routes = Routes::new(&[
+            (Some(GET), "/files/readme"),
+            (Some(GET), "/files/{name}"),
+            (Some(GET), "/files/{*path}")
+        ]);
+        assert_eq!(routes.dispatch(GET, "/files/readme"), Some(("/files/readme", params(&[]))));
+        assert_eq!(routes.dispatch(GET, "/files/notes"), Some(("/files/{name}", params(&[("name", "notes")]))));
+        assert_eq!(routes.dispatch(GET, "/files/readme/old"), Some(("/files/{*path}", params(&[("path", "readme/old")]))));
+    }
+
+    #[test]
+    fn branch_that_fails_deeper_down_is_backtracked_out_of() {
+        let routes = Routes::new(&[
+            (Some(GET), "/repos/settings/general"),
+            (Some(GET), "/repos/{repo}/issues"),
+            (Some(GET), "/repos/{repo}/issues/{id:u64}"),
+            (Some(GET), "/repos/{*rest}")
+        ]);
+        assert_eq!(routes.dispatch(GET, "/repos/settings/issues"), Some(("/repos/{repo}/issues", params(&[("repo", "settings")]))));
+        assert_eq!(routes.dispatch(GET, "/repos/settings/general"), Some(("/repos/settings/general", params(&[]))));
+        // Neither the static nor the dynamic branch leads anywhere, and none of their values stay behind
+        assert_eq!(routes.dispatch(GET, "/repos/altaria/issues/new"), Some(("/repos/{*rest}", params(&[("rest", "altaria/issues/new")]))));
+        assert_eq!(routes.dispatch(GET, "/repos/settings/advanced"), Some(("/repos/{*rest}", params(&[("rest", "settings/advanced")]))));
+    }
+
+    #[test]
+    fn optional_segments_match_with_and_without_them() {
+        let routes = Routes::new(&[(Some(GET), "/posts/{page?}"), (Some(GET), "/users/{id?}/edit")]);
+        assert_eq!(routes.dispatch(GET, "/posts"), Some(("/posts/{page?}", params(&[]))));
+        assert_eq!(routes.dispatch(GET, "/posts/2"), Some(("/posts/{page?}", params(&[("page", "2")]))));
+        assert_eq!(routes.dispatch(GET, "/users/edit"), Some(("/users/{id?}/edit", params(&[]))));
+        assert_eq!(routes.dispatch(GET, "/users/7/edit"), Some(("/users/{id?}/edit", params(&[("id", "7")]))));
+        assert_eq!(routes.pattern(GET, "/posts/2/3"), None);
+    }
+
+    #[test]
+    fn catch_all_needs_a_segment_unless_optional() {
+        let routes = Routes::new(&[(Some(GET), "/files/{*path}"), (Some(GET), "/assets/{*path?}")]);
+        assert_eq!(routes.pattern(GET, "/files"), None);
+        assert_eq!(routes.pattern(GET, "/files/"), None);
+        assert_eq!(routes.dispatch(GET, "/files/a"), Some(("/files/{*path}", params(&[("path", "a")]))));
+        assert_eq!(routes.dispatch(GET, "/assets"), Some(("/assets/{*path?}", params(&[]))));
+        assert_eq!(routes.dispatch(GET, "/assets/css/site.css"), Some(("/assets/{*path?}", params(&[("path", "css/site.css")]))));
+    }
+}
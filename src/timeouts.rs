@@ -0,0 +1,30 @@
+use std::time::Duration;
+
+/// How long the server waits on clients before giving up on them, which keeps slow or
+/// stalled clients from holding connections open forever.
+#[derive(Debug, Clone, Copy)]
+pub struct Timeouts {
+    /// How long a connection may sit idle between requests. Defaults to 5 seconds.
+    pub keep_alive: Duration,
+    /// How long a client has to send the whole request head once it starts sending it,
+    /// which also bounds the TLS handshake and PROXY protocol header. Answered with
+    /// `408 Request Timeout`. Defaults to 10 seconds.
+    pub request_head: Duration,
+    /// How long reading the request body may go without receiving anything. Defaults to
+    /// 30 seconds.
+    pub request_body: Duration,
+    /// How long writing the response may go without the client taking in anything. Time
+    /// spent producing a streamed body isn't counted. Defaults to 30 seconds.
+    pub response_write: Duration
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            keep_alive: Duration::from_secs(5),
+            request_head: Duration::from_secs(10),
+            request_body: Duration::from_secs(30),
+            response_write: Duration::from_secs(30)
+        }
+    }
+}
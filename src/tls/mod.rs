@@ -0,0 +1,280 @@
+mod peer;
+
+pub use peer::{PeerCertificate, SubjectAltName};
+
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::danger::ClientCertVerifier;
+use rustls::server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier};
+use rustls::sign::CertifiedKey;
+use rustls::{RootCertStore, ServerConfig};
+use std::fmt::{Debug, Formatter};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock, Weak};
+use std::time::{Duration, SystemTime};
+use thiserror::Error;
+use tokio_rustls::TlsAcceptor;
+
+const DEFAULT_RELOAD_INTERVAL: Duration = Duration::from_secs(30);
+
+pub(crate) const ALPN_HTTP2: &[u8] = b"h2";
+pub(crate) const ALPN_HTTP1: &[u8] = b"http/1.1";
+
+/// Where to load the server certificate from. Both files are PEM-encoded, and are checked
+/// for changes periodically so that renewed certificates are picked up by new connections
+/// without restarting the server.
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    cert_chain: PathBuf,
+    private_key: PathBuf,
+    reload_interval: Option<Duration>,
+    client_auth: Option<ClientAuth>
+}
+
+/// Whether clients are asked for a certificate, which is verified against the PEM-encoded
+/// CA bundle at the given path. Handlers can get it through the [PeerCertificate] extractor.
+#[derive(Debug, Clone)]
+pub enum ClientAuth {
+    /// Clients without a certificate are let through, but invalid certificates are not
+    Optional(PathBuf),
+    Required(PathBuf)
+}
+
+#[derive(Debug, Error)]
+pub enum TlsError {
+    #[error("Failed to read the certificate chain from {0}: {1}")]
+    InvalidCertificateChain(PathBuf, String),
+    #[error("No certificates were found in {0}")]
+    EmptyCertificateChain(PathBuf),
+    #[error("Failed to read the private key from {0}: {1}")]
+    InvalidPrivateKey(PathBuf, String),
+    #[error("The private key can't be used with the certificate: {0}")]
+    MismatchedKey(rustls::Error),
+    #[error("Failed to read the client CA bundle from {0}: {1}")]
+    InvalidClientCaBundle(PathBuf, String),
+    #[error("Failed to set up client certificate verification: {0}")]
+    ClientVerifier(rustls::server::VerifierBuilderError),
+    #[error("Failed to set up TLS: {0}")]
+    Configuration(rustls::Error)
+}
+
+impl TlsConfig {
+    pub fn from_pem_files<C, K>(cert_chain: C, private_key: K) -> Self
+    where
+        C: Into<PathBuf>,
+        K: Into<PathBuf>
+    {
+        TlsConfig {
+            cert_chain: cert_chain.into(),
+            private_key: private_key.into(),
+            reload_interval: Some(DEFAULT_RELOAD_INTERVAL),
+            client_auth: None
+        }
+    }
+
+    pub fn client_auth(mut self, client_auth: ClientAuth) -> Self {
+        self.client_auth = Some(client_auth);
+        self
+    }
+
+    /// How often the files are checked for changes.
+    pub fn reload_interval(mut self, interval: Duration) -> Self {
+        self.reload_interval = Some(interval);
+        self
+    }
+
+    /// Keeps the certificate loaded at startup for as long as the server runs.
+    pub fn without_reload(mut self) -> Self {
+        self.reload_interval = None;
+        self
+    }
+
+    /// Loads the certificate and builds an acceptor offering the given ALPN protocols,
+    /// in order of preference. Must be called within a Tokio runtime if reloading is on.
+    pub(crate) fn acceptor(&self, alpn_protocols: &[&[u8]]) -> Result<TlsAcceptor, TlsError> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let resolver = Arc::new(ReloadingResolver {
+            current: RwLock::new(Arc::new(load_certified_key(&self.cert_chain, &self.private_key, &provider)?)),
+            provider: provider.clone(),
+            config: self.clone()
+        });
+        if let Some(interval) = self.reload_interval {
+            tokio::spawn(watch_files(Arc::downgrade(&resolver), interval));
+        }
+
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(TlsError::Configuration)?;
+        let builder = match &self.client_auth {
+            Some(client_auth) => builder.with_client_cert_verifier(client_auth.verifier(provider)?),
+            None => builder.with_no_client_auth()
+        };
+        let mut config = builder.with_cert_resolver(resolver);
+        config.alpn_protocols = alpn_protocols.iter().map(|protocol| protocol.to_vec()).collect();
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
+
+impl ClientAuth {
+    fn verifier(&self, provider: Arc<CryptoProvider>) -> Result<Arc<dyn ClientCertVerifier>, TlsError> {
+        let (ca_bundle, required) = match self {
+            ClientAuth::Optional(ca_bundle) => (ca_bundle, false),
+            ClientAuth::Required(ca_bundle) => (ca_bundle, true)
+        };
+        let invalid_bundle = |message: String| TlsError::InvalidClientCaBundle(ca_bundle.clone(), message);
+
+        let mut roots = RootCertStore::empty();
+        for certificate in CertificateDer::pem_file_iter(ca_bundle).map_err(|e| invalid_bundle(e.to_string()))? {
+            let certificate = certificate.map_err(|e| invalid_bundle(e.to_string()))?;
+            roots.add(certificate).map_err(|e| invalid_bundle(e.to_string()))?;
+        }
+        if roots.is_empty() {
+            return Err(invalid_bundle("no certificates found".to_string()));
+        }
+
+        let builder = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+        let builder = if required { builder } else { builder.allow_unauthenticated() };
+        builder.build().map_err(TlsError::ClientVerifier)
+    }
+}
+
+fn load_certified_key(cert_chain: &Path, private_key: &Path, provider: &CryptoProvider) -> Result<CertifiedKey, TlsError> {
+    let certificates = CertificateDer::pem_file_iter(cert_chain)
+        .and_then(|certificates| certificates.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| TlsError::InvalidCertificateChain(cert_chain.to_path_buf(), e.to_string()))?;
+    if certificates.is_empty() {
+        return Err(TlsError::EmptyCertificateChain(cert_chain.to_path_buf()));
+    }
+    let key = PrivateKeyDer::from_pem_file(private_key)
+        .map_err(|e| TlsError::InvalidPrivateKey(private_key.to_path_buf(), e.to_string()))?;
+    CertifiedKey::from_der(certificates, key, provider).map_err(TlsError::MismatchedKey)
+}
+
+/// Hands out the current certificate for every handshake, which the watcher task swaps
+/// whenever the files change.
+struct ReloadingResolver {
+    current: RwLock<Arc<CertifiedKey>>,
+    provider: Arc<CryptoProvider>,
+    config: TlsConfig
+}
+
+impl ResolvesServerCert for ReloadingResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().unwrap().clone())
+    }
+}
+
+impl Debug for ReloadingResolver {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReloadingResolver")
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+/// Reloads the certificate whenever either file's modification time changes, for as long
+/// as the acceptor using it is alive. A certificate that fails to load is reported and the
+/// previous one is kept.
+async fn watch_files(resolver: Weak<ReloadingResolver>, interval: Duration) {
+    let modified_at = |config: &TlsConfig| (modification_time(&config.cert_chain), modification_time(&config.private_key));
+    let mut last_modified = match resolver.upgrade() {
+        Some(resolver) => modified_at(&resolver.config),
+        None => return
+    };
+
+    loop {
+        tokio::time::sleep(interval).await;
+        let Some(resolver) = resolver.upgrade() else {
+            return;
+        };
+        let modified = modified_at(&resolver.config);
+        if modified == last_modified {
+            continue;
+        }
+        last_modified = modified;
+
+        match load_certified_key(&resolver.config.cert_chain, &resolver.config.private_key, &resolver.provider) {
+            Ok(certified_key) => {
+                *resolver.current.write().unwrap() = Arc::new(certified_key);
+                #[cfg(feature = "logging")]
+                tracing::info!("Reloaded TLS certificate from {}", resolver.config.cert_chain.display());
+            },
+            Err(_e) => {
+                #[cfg(feature = "logging")]
+                tracing::error!("Failed to reload TLS certificate: {}", _e);
+            }
+        }
+    }
+}
+
+fn modification_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+
+    fn testdata(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("src/tls/testdata").join(name)
+    }
+
+    fn provider() -> Arc<CryptoProvider> {
+        Arc::new(rustls::crypto::ring::default_provider())
+    }
+
+    #[test]
+    fn key_of_another_certificate_is_refused() {
+        let loaded = load_certified_key(&testdata("cert.pem"), &testdata("renewed-key.pem"), &provider());
+        assert!(matches!(loaded, Err(TlsError::MismatchedKey(_))), "{loaded:?}");
+        assert!(load_certified_key(&testdata("renewed-cert.pem"), &testdata("renewed-key.pem"), &provider()).is_ok());
+    }
+
+    /// Replaces the file with another one, moving its modification time forward so that
+    /// the change is seen even on filesystems with coarse timestamps.
+    fn replace(path: &Path, contents: &[u8], generation: u64) {
+        std::fs::write(path, contents).unwrap();
+        let modified = SystemTime::now() + Duration::from_secs(generation);
+        File::options().write(true).open(path).unwrap().set_modified(modified).unwrap();
+    }
+
+    #[tokio::test]
+    async fn certificate_is_swapped_when_the_files_change() {
+        let directory = std::env::temp_dir().join(format!("altaria-tls-reload-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let (cert_chain, private_key) = (directory.join("cert.pem"), directory.join("key.pem"));
+        std::fs::copy(testdata("cert.pem"), &cert_chain).unwrap();
+        std::fs::copy(testdata("key.pem"), &private_key).unwrap();
+
+        let provider = provider();
+        let config = TlsConfig::from_pem_files(&cert_chain, &private_key).reload_interval(Duration::from_millis(10));
+        let resolver = Arc::new(ReloadingResolver {
+            current: RwLock::new(Arc::new(load_certified_key(&cert_chain, &private_key, &provider).unwrap())),
+            provider,
+            config
+        });
+        tokio::spawn(watch_files(Arc::downgrade(&resolver), Duration::from_millis(10)));
+        let original = resolver.current.read().unwrap().clone();
+
+        // A certificate that fails to load leaves the previous one in place
+        replace(&cert_chain, b"not a certificate", 1);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(Arc::ptr_eq(&resolver.current.read().unwrap(), &original));
+
+        replace(&private_key, &std::fs::read(testdata("renewed-key.pem")).unwrap(), 2);
+        replace(&cert_chain, &std::fs::read(testdata("renewed-cert.pem")).unwrap(), 3);
+        let renewed = load_certified_key(&testdata("renewed-cert.pem"), &testdata("renewed-key.pem"), &resolver.provider).unwrap();
+        let mut swapped = false;
+        for _ in 0..100 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            if resolver.current.read().unwrap().cert == renewed.cert {
+                swapped = true;
+                break;
+            }
+        }
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert!(swapped);
+    }
+}
@@ -0,0 +1,94 @@
+use crate::extractor::{ExtractorError, FromRequest};
+use crate::request::HttpRequest;
+use async_trait::async_trait;
+use rustls::pki_types::CertificateDer;
+use std::net::IpAddr;
+use x509_parser::certificate::X509Certificate;
+use x509_parser::extensions::GeneralName;
+use x509_parser::prelude::FromDer;
+
+/// The certificate chain a client authenticated with, already verified against the
+/// configured CA bundle. The end-entity certificate comes first.
+#[derive(Debug, Clone)]
+pub struct PeerCertificate {
+    chain: Vec<CertificateDer<'static>>,
+    subject: String,
+    subject_alt_names: Vec<SubjectAltName>
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum SubjectAltName {
+    Dns(String),
+    Email(String),
+    Uri(String),
+    Ip(IpAddr)
+}
+
+impl PeerCertificate {
+    /// Returns `None` if the end-entity certificate can't be parsed, which can't happen
+    /// to chains rustls has verified.
+    pub(crate) fn from_chain(chain: &[CertificateDer<'static>]) -> Option<Self> {
+        let (_, certificate) = X509Certificate::from_der(chain.first()?).ok()?;
+        let subject_alt_names = certificate
+            .subject_alternative_name()
+            .ok()
+            .flatten()
+            .map(|extension| extension.value.general_names.iter().filter_map(SubjectAltName::from_general_name).collect())
+            .unwrap_or_default();
+
+        Some(PeerCertificate {
+            chain: chain.to_vec(),
+            subject: certificate.subject().to_string(),
+            subject_alt_names
+        })
+    }
+
+    pub fn chain(&self) -> &[CertificateDer<'static>] {
+        &self.chain
+    }
+
+    pub fn end_entity(&self) -> &CertificateDer<'static> {
+        &self.chain[0]
+    }
+
+    /// The subject's distinguished name, such as `CN=client, O=Example`.
+    pub fn subject(&self) -> &str {
+        &self.subject
+    }
+
+    pub fn subject_alt_names(&self) -> &[SubjectAltName] {
+        &self.subject_alt_names
+    }
+}
+
+impl SubjectAltName {
+    fn from_general_name(name: &GeneralName) -> Option<Self> {
+        match name {
+            GeneralName::DNSName(name) => Some(SubjectAltName::Dns(name.to_string())),
+            GeneralName::RFC822Name(email) => Some(SubjectAltName::Email(email.to_string())),
+            GeneralName::URI(uri) => Some(SubjectAltName::Uri(uri.to_string())),
+            GeneralName::IPAddress(bytes) => match bytes.len() {
+                4 => <[u8; 4]>::try_from(*bytes).ok().map(|ip| SubjectAltName::Ip(ip.into())),
+                16 => <[u8; 16]>::try_from(*bytes).ok().map(|ip| SubjectAltName::Ip(ip.into())),
+                _ => None
+            },
+            _ => None
+        }
+    }
+}
+
+#[async_trait]
+impl FromRequest for PeerCertificate {
+    async fn from_request(_index: usize, request: &mut HttpRequest) -> Result<Self, ExtractorError>
+    where Self: Sized {
+        request.connection.peer_certificate.clone().ok_or(ExtractorError::MissingPeerCertificate)
+    }
+}
+
+#[async_trait]
+impl FromRequest for Option<PeerCertificate> {
+    async fn from_request(_index: usize, request: &mut HttpRequest) -> Result<Self, ExtractorError>
+    where Self: Sized {
+        Ok(request.connection.peer_certificate.clone())
+    }
+}